@@ -0,0 +1,47 @@
+//! Reading files through a buffer versus memory-mapping them, measured
+//! through the same `Searcher::print` that `minigrep::run` uses.
+//!
+//! Run with `cargo bench --bench input`.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use minigrep::{Config, Searcher};
+
+const POEM: &str = include_str!("../poem.txt");
+
+fn searcher(args: &[&str], path: &Path) -> Searcher {
+    let args: Vec<String> = ["minigrep"]
+        .iter()
+        .chain(args)
+        .map(|s| s.to_string())
+        .chain([path.display().to_string()])
+        .collect();
+    Searcher::new(Config::build(&args).unwrap()).unwrap()
+}
+
+fn input(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("input");
+
+    // from well under the auto threshold to well over it
+    for size in [64 << 10, 4 << 20, 64 << 20] {
+        let path = dir.path().join(format!("poem-{size}.txt"));
+        fs::write(&path, POEM.repeat(size / POEM.len() + 1)).unwrap();
+        group.throughput(Throughput::Bytes(fs::metadata(&path).unwrap().len()));
+
+        for mmap in ["never", "always", "auto"] {
+            let searcher = searcher(&["-c", &format!("--mmap={mmap}"), "frog"], &path);
+            group.bench_with_input(BenchmarkId::new(mmap, size), &searcher, |b, searcher| {
+                b.iter(|| searcher.print(io::sink()).unwrap())
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, input);
+criterion_main!(benches);
@@ -0,0 +1,99 @@
+//! How fast each kind of query gets through `poem.txt` copied over and
+//! over, searched in memory so only the matching itself is measured.
+//!
+//! Run with `cargo bench --bench search`. To compare two versions, run
+//! `cargo bench --bench search -- --save-baseline before` on the old one
+//! and `cargo bench --bench search -- --baseline before` on the new one.
+
+use std::io::{self, BufRead};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use minigrep::{Config, Entry, Matcher};
+
+const POEM: &str = include_str!("../poem.txt");
+
+const QUERIES: &[(&str, &[&str])] = &[
+    // one hit every few lines
+    ("literal", &["frog"]),
+    // never found, so every byte is looked at
+    ("absent", &["toad"]),
+    ("ignore-case", &["-i", "FROG"]),
+    ("several", &["-e", "frog", "-e", "bog", "-e", "day"]),
+    ("word", &["-w", "are"]),
+    ("regex", &["-E", r"fr[aeiou]g"]),
+];
+
+fn matcher(args: &[&str]) -> Matcher {
+    let args: Vec<String> = ["minigrep"]
+        .iter()
+        .chain(args)
+        .map(|s| s.to_string())
+        .collect();
+    Matcher::new(&Config::build(&args).unwrap()).unwrap()
+}
+
+fn count(matcher: &Matcher, contents: impl BufRead) -> usize {
+    let mut count = 0;
+    matcher
+        .search_reader(contents, false, 0, 0, |entry| {
+            if let Entry::Match(_) = entry {
+                count += 1;
+            }
+            Ok(true)
+        })
+        .unwrap();
+    count
+}
+
+fn queries(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+
+    for size in [1 << 20, 16 << 20] {
+        let contents = POEM.repeat(size / POEM.len() + 1);
+        group.throughput(Throughput::Bytes(contents.len() as u64));
+
+        for (name, args) in QUERIES {
+            let matcher = matcher(args);
+            group.bench_with_input(BenchmarkId::new(*name, size), &contents, |b, contents| {
+                b.iter(|| count(&matcher, contents.as_bytes()))
+            });
+        }
+
+        // the `search` function goes through the same matcher, but also
+        // collects every result
+        group.bench_with_input(
+            BenchmarkId::new("search-fn", size),
+            &contents,
+            |b, contents| b.iter(|| minigrep::search("frog", contents).len()),
+        );
+
+        // what minigrep started out doing, for comparison: every line
+        // checked on its own with `str::contains`
+        group.bench_with_input(
+            BenchmarkId::new("contains", size),
+            &contents,
+            |b, contents| {
+                b.iter(|| {
+                    contents
+                        .lines()
+                        .filter(|line| line.contains("frog"))
+                        .count()
+                })
+            },
+        );
+
+        // the same query through a reader that hands over 8 KiB at a time,
+        // like a file that isn't memory-mapped
+        let matcher = matcher(&["frog"]);
+        group.bench_with_input(
+            BenchmarkId::new("buffered", size),
+            &contents,
+            |b, contents| b.iter(|| count(&matcher, io::BufReader::new(contents.as_bytes()))),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, queries);
+criterion_main!(benches);
@@ -0,0 +1,175 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::ops::Range;
+
+use crate::config::ColorChoice;
+
+const RESET: &str = "\x1b[0m";
+
+/// The ANSI escape sequence each part of the output is painted with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colors {
+    pub path: String,
+    /// Line numbers and byte offsets.
+    pub number: String,
+    pub separator: String,
+    pub matched: String,
+}
+
+impl Default for Colors {
+    fn default() -> Colors {
+        Colors {
+            path: String::from("\x1b[35m"),
+            number: String::from("\x1b[32m"),
+            separator: String::from("\x1b[36m"),
+            matched: String::from("\x1b[1;31m"),
+        }
+    }
+}
+
+/// Turns a style such as `"bold red"` or `"underline bright-blue"` into its
+/// escape sequence: any number of `bold`, `dim`, `italic` and `underline`,
+/// and at most one of the eight colours, optionally `bright-`. `"none"`
+/// leaves the text unstyled. Returns `None` for anything else.
+pub(crate) fn parse_style(style: &str) -> Option<String> {
+    const COLORS: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+
+    let mut codes = Vec::new();
+    let mut colored = false;
+    for word in style.split_whitespace() {
+        let code = match word {
+            "none" => continue,
+            "bold" => 1,
+            "dim" => 2,
+            "italic" => 3,
+            "underline" => 4,
+            _ if colored => return None,
+            _ => {
+                colored = true;
+                let (base, name) = match word.strip_prefix("bright-") {
+                    Some(name) => (90, name),
+                    None => (30, word),
+                };
+                base + COLORS.iter().position(|&color| color == name)?
+            }
+        };
+        codes.push(code.to_string());
+    }
+
+    if codes.is_empty() {
+        Some(String::new())
+    } else {
+        Some(format!("\x1b[{}m", codes.join(";")))
+    }
+}
+
+/// Wraps pieces of output in ANSI colour codes, or leaves them alone when
+/// colour is turned off.
+pub(crate) struct Palette {
+    enabled: bool,
+    matched: String,
+}
+
+/// Settles `Auto` for output that goes to stdout.
+pub(crate) fn for_stdout(choice: ColorChoice) -> ColorChoice {
+    // https://no-color.org: any non-empty NO_COLOR turns colour off
+    match choice {
+        ColorChoice::Auto
+            if io::stdout().is_terminal()
+                && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) =>
+        {
+            ColorChoice::Always
+        }
+        ColorChoice::Auto => ColorChoice::Never,
+        choice => choice,
+    }
+}
+
+impl Palette {
+    pub(crate) fn new(choice: ColorChoice, colors: &Colors) -> Palette {
+        // there's no telling where an arbitrary writer ends up, so `Auto`
+        // only means colour once `for_stdout` has settled it
+        let enabled = choice == ColorChoice::Always;
+
+        Palette {
+            enabled,
+            matched: colors.matched.clone(),
+        }
+    }
+
+    pub(crate) fn paint(&self, style: &str, text: &str) -> String {
+        if self.enabled && !style.is_empty() {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Paints the parts of `line` covered by `spans` as matches.
+    pub(crate) fn highlight(&self, line: &str, spans: &[Range<usize>]) -> String {
+        if !self.enabled {
+            return line.to_string();
+        }
+
+        let mut out = String::with_capacity(line.len());
+        let mut last = 0;
+
+        for span in spans.iter().filter(|span| !span.is_empty()) {
+            out.push_str(&line[last..span.start]);
+            out.push_str(&self.paint(&self.matched, &line[span.clone()]));
+            last = span.end;
+        }
+        out.push_str(&line[last..]);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlights_each_span() {
+        let palette = Palette::new(ColorChoice::Always, &Colors::default());
+
+        assert_eq!(
+            "a \x1b[1;31mfrog\x1b[0m and a \x1b[1;31mfrog\x1b[0m",
+            palette.highlight("a frog and a frog", &[2..6, 13..17])
+        );
+    }
+
+    #[test]
+    fn never_leaves_text_alone() {
+        let palette = Palette::new(ColorChoice::Never, &Colors::default());
+
+        assert_eq!(
+            "a frog and a frog",
+            palette.highlight("a frog and a frog", &[2..6, 13..17])
+        );
+        assert_eq!(
+            "poem.txt",
+            palette.paint(&Colors::default().path, "poem.txt")
+        );
+    }
+
+    #[test]
+    fn auto_is_plain_unless_settled() {
+        let palette = Palette::new(ColorChoice::Auto, &Colors::default());
+
+        assert_eq!("a frog", palette.highlight("a frog", &[0..1, 2..6]));
+        assert_eq!(ColorChoice::Never, for_stdout(ColorChoice::Never));
+        assert_eq!(ColorChoice::Always, for_stdout(ColorChoice::Always));
+    }
+
+    #[test]
+    fn styles_are_parsed() {
+        assert_eq!(Some(String::from("\x1b[1;31m")), parse_style("bold red"));
+        assert_eq!(Some(String::from("\x1b[94m")), parse_style("bright-blue"));
+        assert_eq!(Some(String::new()), parse_style("none"));
+        assert_eq!(None, parse_style("red blue"));
+        assert_eq!(None, parse_style("mauve"));
+    }
+}
@@ -0,0 +1,1324 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+use crate::{Colors, ConfigFile};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// What to search for; a line is selected if any of these match.
+    pub patterns: Vec<String>,
+    pub file_paths: Vec<String>,
+    pub ignore_case: bool,
+    /// Ignore case unless the query contains an uppercase letter.
+    pub smart_case: bool,
+    /// Whether the config file or command line said how to treat case,
+    /// with `-i`, `-S`, `-s` or `--no-smart-case`. Only when neither did
+    /// does `IGNORE_CASE` in the environment get a say.
+    pub case_chosen: bool,
+    pub regex: bool,
+    /// Let matches span line breaks; the whole input is read at once.
+    pub multiline: bool,
+    /// Only count matches that form whole words.
+    pub word_regexp: bool,
+    /// Only count matches that cover the whole line.
+    pub line_regexp: bool,
+    pub follow_links: bool,
+    /// Search hidden files and directories while walking.
+    pub hidden: bool,
+    /// Decompress gzip, bzip2, xz and zstd input before searching it.
+    pub search_zip: bool,
+    /// Honour `.gitignore` and `.ignore` files while walking.
+    pub use_ignore_files: bool,
+    /// Include (or with a leading `!`, exclude) files matching these globs.
+    pub globs: Vec<String>,
+    /// Only search files of these types, e.g. `rust`.
+    pub types: Vec<String>,
+    /// Never search files of these types.
+    pub types_not: Vec<String>,
+    /// File types defined on top of the built-in ones, as a name and a
+    /// glob; a type with several globs appears once for each.
+    pub type_defs: Vec<(String, String)>,
+    /// How many files to search at once.
+    pub threads: usize,
+    pub line_number: bool,
+    pub byte_offset: bool,
+    pub invert_match: bool,
+    pub output: OutputMode,
+    pub color: ColorChoice,
+    pub colors: Colors,
+    /// Whether files are memory-mapped rather than read.
+    pub mmap: MmapChoice,
+    pub before_context: usize,
+    pub after_context: usize,
+    /// Print matching lines with each match replaced by this.
+    pub replace: Option<String>,
+    /// Write replacements back to the files instead of printing them.
+    pub in_place: bool,
+    /// Appended to a file's name for the copy kept by `in_place`; empty
+    /// means no backup.
+    pub backup_suffix: String,
+    /// Match approximately, within this many edits, and print the closest
+    /// lines first.
+    pub fuzzy: Option<usize>,
+    /// With `fuzzy`, swapping two neighbouring characters is a single edit.
+    pub transpositions: bool,
+    /// Build or refresh the trigram index of each path, which must be a
+    /// directory, instead of searching; set by the `index` subcommand.
+    pub build_index: bool,
+    /// Skip files that the index says can't match.
+    pub use_index: bool,
+}
+
+/// What `run` prints for each searched file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// The selected lines themselves.
+    Lines,
+    /// Only how many lines were selected.
+    Count,
+    /// Only the name of the file, if any line was selected.
+    FilesWithMatches,
+    /// Only the name of the file, if no line was selected.
+    FilesWithoutMatch,
+    /// JSON Lines records describing each file and match, for other
+    /// programs to read.
+    Json,
+}
+
+/// Whether matches are highlighted with ANSI colours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when `run` prints to a terminal and `NO_COLOR` isn't set;
+    /// `Searcher::print` can't tell where its writer goes, so there it
+    /// means never.
+    Auto,
+    Always,
+    Never,
+}
+
+/// Whether input files are memory-mapped instead of read bit by bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmapChoice {
+    /// Only files big enough for it to pay off.
+    Auto,
+    Always,
+    Never,
+}
+
+/// Why `Config::build` didn't produce a `Config`.
+///
+/// `Help` and `Version` aren't really failures: the caller should print
+/// them to stdout and exit successfully.
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    Help,
+    Version,
+    NotEnoughArguments,
+    UnknownOption(String),
+    MissingValue(String),
+    UnexpectedValue(String),
+    InvalidValue(String, String),
+    /// The first option only makes sense together with the second.
+    Requires(String, String),
+    /// The two options can't be used together.
+    Conflicts(String, String),
+    /// A `-f` file couldn't be read; holds the path and the reason.
+    PatternFile(String, String),
+    /// `minigrep index` was given something other than a directory.
+    NotADirectory(String),
+    /// The config file couldn't be read or parsed; holds the path and the
+    /// reason.
+    ConfigFile(String, String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "{}", help()),
+            ArgsError::Version => write!(f, "minigrep {}", env!("CARGO_PKG_VERSION")),
+            ArgsError::NotEnoughArguments => write!(f, "not enough arguments"),
+            ArgsError::UnknownOption(arg) => write!(f, "unrecognized option '{arg}'"),
+            ArgsError::MissingValue(arg) => write!(f, "option '{arg}' requires an argument"),
+            ArgsError::UnexpectedValue(arg) => {
+                write!(f, "option '{arg}' doesn't allow an argument")
+            }
+            ArgsError::InvalidValue(arg, value) => {
+                write!(f, "invalid value '{value}' for option '{arg}'")
+            }
+            ArgsError::Requires(arg, other) => write!(f, "option '{arg}' requires '{other}'"),
+            ArgsError::Conflicts(arg, other) => {
+                write!(f, "option '{arg}' can't be used with '{other}'")
+            }
+            ArgsError::PatternFile(path, reason) => {
+                write!(f, "cannot read pattern file '{path}': {reason}")
+            }
+            ArgsError::NotADirectory(path) => write!(
+                f,
+                "'{path}' is not a directory; `minigrep index` builds indexes of \
+                 directories (to search for the word index, use -e index)"
+            ),
+            ArgsError::ConfigFile(path, reason) => {
+                write!(f, "cannot use config file '{path}': {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+struct Opt {
+    short: Option<char>,
+    long: &'static str,
+    // name of the value shown in --help, `None` for plain switches
+    value: Option<&'static str>,
+    help: &'static str,
+}
+
+// the single source of truth for both parsing and --help
+const OPTIONS: &[Opt] = &[
+    Opt {
+        short: Some('e'),
+        long: "regexp",
+        value: Some("QUERY"),
+        help: "Search for QUERY; may be given more than once",
+    },
+    Opt {
+        short: Some('f'),
+        long: "file",
+        value: Some("FILE"),
+        help: "Search for each non-empty line of FILE",
+    },
+    Opt {
+        short: Some('i'),
+        long: "ignore-case",
+        value: None,
+        help: "Search case-insensitively (the default if IGNORE_CASE is set)",
+    },
+    Opt {
+        short: Some('S'),
+        long: "smart-case",
+        value: None,
+        help: "Ignore case unless QUERY contains an uppercase letter",
+    },
+    Opt {
+        short: Some('s'),
+        long: "case-sensitive",
+        value: None,
+        help: "Match case exactly, undoing -i and -S",
+    },
+    Opt {
+        short: None,
+        long: "no-smart-case",
+        value: None,
+        help: "Undo -S",
+    },
+    Opt {
+        short: Some('E'),
+        long: "regex",
+        value: None,
+        help: "Treat QUERY as a regular expression",
+    },
+    Opt {
+        short: None,
+        long: "no-regex",
+        value: None,
+        help: "Treat QUERY as literal text again",
+    },
+    Opt {
+        short: Some('U'),
+        long: "multiline",
+        value: None,
+        help: "Let matches span lines, e.g. with \\n in a regex",
+    },
+    Opt {
+        short: None,
+        long: "no-multiline",
+        value: None,
+        help: "Undo -U",
+    },
+    Opt {
+        short: None,
+        long: "fuzzy",
+        value: Some("EDITS"),
+        help: "Match within EDITS typos, closest lines first",
+    },
+    Opt {
+        short: None,
+        long: "no-fuzzy",
+        value: None,
+        help: "Match exactly again, undoing --fuzzy",
+    },
+    Opt {
+        short: None,
+        long: "transpositions",
+        value: None,
+        help: "With --fuzzy, count swapped neighbours as one typo",
+    },
+    Opt {
+        short: None,
+        long: "no-transpositions",
+        value: None,
+        help: "Undo --transpositions",
+    },
+    Opt {
+        short: Some('w'),
+        long: "word-regexp",
+        value: None,
+        help: "Only match whole words",
+    },
+    Opt {
+        short: None,
+        long: "no-word-regexp",
+        value: None,
+        help: "Undo -w",
+    },
+    Opt {
+        short: Some('x'),
+        long: "line-regexp",
+        value: None,
+        help: "Only match whole lines",
+    },
+    Opt {
+        short: None,
+        long: "no-line-regexp",
+        value: None,
+        help: "Undo -x",
+    },
+    Opt {
+        short: Some('n'),
+        long: "line-number",
+        value: None,
+        help: "Prefix each line with its line number",
+    },
+    Opt {
+        short: Some('N'),
+        long: "no-line-number",
+        value: None,
+        help: "Don't print line numbers",
+    },
+    Opt {
+        short: Some('b'),
+        long: "byte-offset",
+        value: None,
+        help: "Prefix each line with its byte offset in the file",
+    },
+    Opt {
+        short: None,
+        long: "no-byte-offset",
+        value: None,
+        help: "Don't print byte offsets",
+    },
+    Opt {
+        short: Some('v'),
+        long: "invert-match",
+        value: None,
+        help: "Print lines that do not match",
+    },
+    Opt {
+        short: None,
+        long: "no-invert-match",
+        value: None,
+        help: "Undo -v",
+    },
+    Opt {
+        short: Some('c'),
+        long: "count",
+        value: None,
+        help: "Print only the number of matching lines per file",
+    },
+    Opt {
+        short: None,
+        long: "no-count",
+        value: None,
+        help: "Undo -c",
+    },
+    Opt {
+        short: Some('l'),
+        long: "files-with-matches",
+        value: None,
+        help: "Print only the names of files with a match",
+    },
+    Opt {
+        short: None,
+        long: "no-files-with-matches",
+        value: None,
+        help: "Undo -l",
+    },
+    Opt {
+        short: Some('L'),
+        long: "files-without-match",
+        value: None,
+        help: "Print only the names of files without a match",
+    },
+    Opt {
+        short: None,
+        long: "no-files-without-match",
+        value: None,
+        help: "Undo -L",
+    },
+    Opt {
+        short: None,
+        long: "json",
+        value: None,
+        help: "Print results as JSON Lines (one object per line)",
+    },
+    Opt {
+        short: None,
+        long: "no-json",
+        value: None,
+        help: "Undo --json",
+    },
+    Opt {
+        short: Some('A'),
+        long: "after-context",
+        value: Some("NUM"),
+        help: "Print NUM lines of context after each match",
+    },
+    Opt {
+        short: Some('B'),
+        long: "before-context",
+        value: Some("NUM"),
+        help: "Print NUM lines of context before each match",
+    },
+    Opt {
+        short: Some('C'),
+        long: "context",
+        value: Some("NUM"),
+        help: "Print NUM lines of context around each match",
+    },
+    Opt {
+        short: Some('r'),
+        long: "replace",
+        value: Some("TEXT"),
+        help: "Print lines with matches replaced by TEXT ($1 is a regex group)",
+    },
+    Opt {
+        short: None,
+        long: "no-replace",
+        value: None,
+        help: "Print lines as they are, undoing -r and --in-place",
+    },
+    Opt {
+        short: None,
+        long: "in-place",
+        value: None,
+        help: "Write replacements back to the files instead of printing",
+    },
+    Opt {
+        short: None,
+        long: "no-in-place",
+        value: None,
+        help: "Undo --in-place",
+    },
+    Opt {
+        short: None,
+        long: "backup-suffix",
+        value: Some("SUFFIX"),
+        help: "Keep originals as FILE+SUFFIX with --in-place (default .bak)",
+    },
+    Opt {
+        short: None,
+        long: "color",
+        value: Some("WHEN"),
+        help: "Highlight matches: auto, always or never",
+    },
+    Opt {
+        short: None,
+        long: "mmap",
+        value: Some("WHEN"),
+        help: "Memory-map files: auto (large ones), always or never",
+    },
+    Opt {
+        short: None,
+        long: "follow",
+        value: None,
+        help: "Follow symbolic links while searching directories",
+    },
+    Opt {
+        short: None,
+        long: "no-follow",
+        value: None,
+        help: "Don't follow symbolic links while searching directories",
+    },
+    Opt {
+        short: Some('z'),
+        long: "search-zip",
+        value: None,
+        help: "Search inside gzip, bzip2, xz and zstd compressed files",
+    },
+    Opt {
+        short: None,
+        long: "no-search-zip",
+        value: None,
+        help: "Undo -z",
+    },
+    Opt {
+        short: None,
+        long: "index",
+        value: None,
+        help: "Skip files the index of PATH (or a parent) rules out",
+    },
+    Opt {
+        short: None,
+        long: "no-index",
+        value: None,
+        help: "Search every file, ignoring any index",
+    },
+    Opt {
+        short: None,
+        long: "hidden",
+        value: None,
+        help: "Search hidden files and directories",
+    },
+    Opt {
+        short: None,
+        long: "no-hidden",
+        value: None,
+        help: "Skip hidden files and directories",
+    },
+    Opt {
+        short: None,
+        long: "ignore",
+        value: None,
+        help: "Skip files listed in .gitignore or .ignore",
+    },
+    Opt {
+        short: None,
+        long: "no-ignore",
+        value: None,
+        help: "Don't skip files listed in .gitignore or .ignore",
+    },
+    Opt {
+        short: Some('g'),
+        long: "glob",
+        value: Some("GLOB"),
+        help: "Only search files matching GLOB, or skip them with !GLOB",
+    },
+    Opt {
+        short: Some('t'),
+        long: "type",
+        value: Some("TYPE"),
+        help: "Only search files of TYPE, such as rust or md",
+    },
+    Opt {
+        short: Some('T'),
+        long: "type-not",
+        value: Some("TYPE"),
+        help: "Don't search files of TYPE",
+    },
+    Opt {
+        short: Some('j'),
+        long: "threads",
+        value: Some("NUM"),
+        help: "Search NUM files in parallel (0 picks one per CPU)",
+    },
+    Opt {
+        short: None,
+        long: "no-config",
+        value: None,
+        help: "Don't read the config file",
+    },
+    Opt {
+        short: Some('h'),
+        long: "help",
+        value: None,
+        help: "Print this help and exit",
+    },
+    Opt {
+        short: Some('V'),
+        long: "version",
+        value: None,
+        help: "Print version information and exit",
+    },
+];
+
+fn help() -> String {
+    let mut text = String::from(
+        "Usage: minigrep [OPTIONS] QUERY [PATH]...\n       \
+         minigrep [OPTIONS] (-e QUERY | -f FILE)... [PATH]...\n       \
+         minigrep index [OPTIONS] [DIR]...\n\n\
+         Search for QUERY in each PATH. Directories are searched recursively;\n\
+         with no PATH, or when PATH is -, read standard input.\n\n\
+         Ignoring case folds plain queries fully, so straße matches STRASSE.\n\
+         With -E or -U the regex engine only folds one character to one\n\
+         other, so there it doesn't.\n\n\
+         `minigrep index` builds or refreshes a trigram index of each DIR\n\
+         (default .) that --index uses to skip files that can't match. To\n\
+         search for the word index, use -e index or -- index.\n\n\
+         Defaults for the options below, colours, globs to ignore and extra\n\
+         file types can be set in ~/.config/minigrep/config.toml, or the file\n\
+         MINIGREP_CONFIG names; options on the command line take precedence,\n\
+         and each switch has an opposite to turn a default back off.\n\n\
+         Options:\n",
+    );
+
+    for opt in OPTIONS {
+        let short = match opt.short {
+            Some(c) => format!("-{c}, "),
+            None => String::from("    "),
+        };
+        let long = match opt.value {
+            Some(value) => format!("--{}={value}", opt.long),
+            None => format!("--{}", opt.long),
+        };
+        text.push_str(&format!("  {short}{long:<26}{}\n", opt.help));
+    }
+
+    text.push_str("  --                            Treat every following argument as QUERY or PATH");
+    text
+}
+
+impl Default for Config {
+    /// What the command line gives with no options: a plain,
+    /// case-sensitive literal search, using every CPU. There are no
+    /// patterns or paths yet.
+    fn default() -> Config {
+        Config {
+            patterns: Vec::new(),
+            file_paths: Vec::new(),
+            ignore_case: false,
+            smart_case: false,
+            case_chosen: false,
+            regex: false,
+            multiline: false,
+            word_regexp: false,
+            line_regexp: false,
+            follow_links: true,
+            hidden: false,
+            search_zip: false,
+            use_ignore_files: true,
+            globs: Vec::new(),
+            types: Vec::new(),
+            types_not: Vec::new(),
+            type_defs: Vec::new(),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            line_number: false,
+            byte_offset: false,
+            invert_match: false,
+            output: OutputMode::Lines,
+            color: ColorChoice::Auto,
+            colors: Colors::default(),
+            mmap: MmapChoice::Auto,
+            before_context: 0,
+            after_context: 0,
+            replace: None,
+            in_place: false,
+            backup_suffix: String::from(".bak"),
+            fuzzy: None,
+            transpositions: false,
+            build_index: false,
+            use_index: false,
+        }
+    }
+}
+
+impl Config {
+    /// Parses the command line, ignoring any config file. `args[0]` is the
+    /// program name, as in `env::args`.
+    pub fn build(args: &[String]) -> Result<Config, ArgsError> {
+        Config::build_with(args, &ConfigFile::default())
+    }
+
+    /// Parses the command line on top of the defaults in `file`. Its `args`
+    /// come first, so an option given on the command line wins over the
+    /// same option there, and its ignore globs come before any `--glob`.
+    pub fn build_with(args: &[String], file: &ConfigFile) -> Result<Config, ArgsError> {
+        let mut config = Config {
+            globs: file.ignore.iter().map(|glob| format!("!{glob}")).collect(),
+            type_defs: file
+                .types
+                .iter()
+                .flat_map(|(name, globs)| globs.iter().map(|glob| (name.clone(), glob.clone())))
+                .collect(),
+            colors: file.colors.clone(),
+            ..Config::default()
+        };
+        let mut positional = Vec::new();
+        // with -e or -f every positional argument is a path
+        let mut patterns_given = false;
+        // the file's args are parsed on their own, so an option there
+        // can't take its value from the command line
+        config.parse_args(file.args.iter(), &mut positional, &mut patterns_given)?;
+        // `index` is a subcommand only as the very first argument
+        let mut command_line = args.iter().skip(1).peekable();
+        config.build_index = command_line.next_if(|arg| *arg == "index").is_some();
+        config.parse_args(command_line, &mut positional, &mut patterns_given)?;
+
+        if config.build_index {
+            if let Some(path) = positional.iter().find(|path| !Path::new(path).is_dir()) {
+                return Err(ArgsError::NotADirectory(path.clone()));
+            }
+            config.file_paths = if positional.is_empty() {
+                vec![String::from(".")]
+            } else {
+                positional
+            };
+            return Ok(config);
+        }
+
+        if !patterns_given {
+            if positional.is_empty() {
+                return Err(ArgsError::NotEnoughArguments);
+            }
+            config.patterns.push(positional.remove(0));
+        }
+
+        // no paths at all means read standard input, like `-` does
+        config.file_paths = if positional.is_empty() {
+            vec![String::from("-")]
+        } else {
+            positional
+        };
+
+        if config.in_place && config.replace.is_none() {
+            return Err(ArgsError::Requires(
+                String::from("--in-place"),
+                String::from("--replace"),
+            ));
+        }
+
+        if config.transpositions && config.fuzzy.is_none() {
+            return Err(ArgsError::Requires(
+                String::from("--transpositions"),
+                String::from("--fuzzy"),
+            ));
+        }
+        if config.fuzzy.is_some() && config.regex {
+            return Err(ArgsError::Conflicts(
+                String::from("--fuzzy"),
+                String::from("--regex"),
+            ));
+        }
+
+        // both work a line at a time
+        for (flag, conflicts) in [
+            ("--fuzzy", config.fuzzy.is_some()),
+            ("--in-place", config.in_place),
+        ] {
+            if config.multiline && conflicts {
+                return Err(ArgsError::Conflicts(
+                    String::from("--multiline"),
+                    String::from(flag),
+                ));
+            }
+        }
+
+        if config.threads == 0 {
+            config.threads = Config::default().threads;
+        }
+
+        Ok(config)
+    }
+
+    /// Applies each option in `args`, collecting the other arguments into
+    /// `positional` and noting whether `-e` or `-f` gave any patterns.
+    fn parse_args<'a>(
+        &mut self,
+        mut args: impl Iterator<Item = &'a String>,
+        positional: &mut Vec<String>,
+        patterns_given: &mut bool,
+    ) -> Result<(), ArgsError> {
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                positional.extend(args.by_ref().cloned());
+                break;
+            }
+
+            if let Some(long) = arg.strip_prefix("--") {
+                let (name, inline) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                let opt = OPTIONS
+                    .iter()
+                    .find(|opt| opt.long == name)
+                    .ok_or_else(|| ArgsError::UnknownOption(format!("--{name}")))?;
+
+                let value = match (opt.value, inline) {
+                    (Some(_), Some(value)) => Some(value),
+                    (Some(_), None) => Some(
+                        args.next()
+                            .cloned()
+                            .ok_or_else(|| ArgsError::MissingValue(format!("--{name}")))?,
+                    ),
+                    (None, Some(_)) => return Err(ArgsError::UnexpectedValue(format!("--{name}"))),
+                    (None, None) => None,
+                };
+                self.apply(opt, &format!("--{name}"), value)?;
+                *patterns_given |= matches!(opt.long, "regexp" | "file");
+            } else if arg.len() > 1 && arg.starts_with('-') {
+                // a cluster of short flags such as `-inv`; an option that
+                // takes a value swallows the rest of the cluster or the
+                // next argument
+                let cluster = &arg[1..];
+                for (i, c) in cluster.char_indices() {
+                    let opt = OPTIONS
+                        .iter()
+                        .find(|opt| opt.short == Some(c))
+                        .ok_or_else(|| ArgsError::UnknownOption(format!("-{c}")))?;
+
+                    if opt.value.is_none() {
+                        self.apply(opt, &format!("-{c}"), None)?;
+                        continue;
+                    }
+
+                    let rest = &cluster[i + c.len_utf8()..];
+                    let value = if rest.is_empty() {
+                        args.next()
+                            .cloned()
+                            .ok_or_else(|| ArgsError::MissingValue(format!("-{c}")))?
+                    } else {
+                        rest.to_string()
+                    };
+                    self.apply(opt, &format!("-{c}"), Some(value))?;
+                    *patterns_given |= matches!(opt.long, "regexp" | "file");
+                    break;
+                }
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    // `name` is the option as the user spelled it, for error messages
+    fn apply(&mut self, opt: &Opt, name: &str, value: Option<String>) -> Result<(), ArgsError> {
+        match opt.long {
+            "regexp" => self.patterns.extend(value),
+            "file" => {
+                let path = value.unwrap_or_default();
+                let contents = fs::read_to_string(&path)
+                    .map_err(|e| ArgsError::PatternFile(path.clone(), e.to_string()))?;
+                self.patterns.extend(
+                    contents
+                        .lines()
+                        .filter(|line| !line.is_empty())
+                        .map(String::from),
+                );
+            }
+            "ignore-case" => {
+                self.ignore_case = true;
+                self.case_chosen = true;
+            }
+            "smart-case" => {
+                self.smart_case = true;
+                self.case_chosen = true;
+            }
+            "case-sensitive" => {
+                self.ignore_case = false;
+                self.smart_case = false;
+                self.case_chosen = true;
+            }
+            "no-smart-case" => {
+                self.smart_case = false;
+                self.case_chosen = true;
+            }
+            "regex" => self.regex = true,
+            "no-regex" => self.regex = false,
+            "multiline" => self.multiline = true,
+            "no-multiline" => self.multiline = false,
+            "word-regexp" => self.word_regexp = true,
+            "no-word-regexp" => self.word_regexp = false,
+            "line-regexp" => self.line_regexp = true,
+            "no-line-regexp" => self.line_regexp = false,
+            "line-number" => self.line_number = true,
+            "no-line-number" => self.line_number = false,
+            "byte-offset" => self.byte_offset = true,
+            "no-byte-offset" => self.byte_offset = false,
+            "invert-match" => self.invert_match = true,
+            "no-invert-match" => self.invert_match = false,
+            "count" => self.output = OutputMode::Count,
+            "files-with-matches" => self.output = OutputMode::FilesWithMatches,
+            "files-without-match" => self.output = OutputMode::FilesWithoutMatch,
+            "json" => self.output = OutputMode::Json,
+            // each only undoes its own mode, not whichever came last
+            "no-count" | "no-files-with-matches" | "no-files-without-match" | "no-json" => {
+                let undone = match opt.long {
+                    "no-count" => OutputMode::Count,
+                    "no-files-with-matches" => OutputMode::FilesWithMatches,
+                    "no-files-without-match" => OutputMode::FilesWithoutMatch,
+                    _ => OutputMode::Json,
+                };
+                if self.output == undone {
+                    self.output = OutputMode::Lines;
+                }
+            }
+            "after-context" => self.after_context = parse_number(name, value)?,
+            "before-context" => self.before_context = parse_number(name, value)?,
+            "context" => {
+                let lines = parse_number(name, value)?;
+                self.before_context = lines;
+                self.after_context = lines;
+            }
+            "color" => {
+                self.color = match value.as_deref() {
+                    Some("auto") => ColorChoice::Auto,
+                    Some("always") => ColorChoice::Always,
+                    Some("never") => ColorChoice::Never,
+                    _ => {
+                        return Err(ArgsError::InvalidValue(
+                            name.to_string(),
+                            value.unwrap_or_default(),
+                        ))
+                    }
+                }
+            }
+            "mmap" => {
+                self.mmap = match value.as_deref() {
+                    Some("auto") => MmapChoice::Auto,
+                    Some("always") => MmapChoice::Always,
+                    Some("never") => MmapChoice::Never,
+                    _ => {
+                        return Err(ArgsError::InvalidValue(
+                            name.to_string(),
+                            value.unwrap_or_default(),
+                        ))
+                    }
+                }
+            }
+            "follow" => self.follow_links = true,
+            "no-follow" => self.follow_links = false,
+            "replace" => self.replace = value,
+            "no-replace" => {
+                self.replace = None;
+                self.in_place = false;
+            }
+            "in-place" => self.in_place = true,
+            "no-in-place" => self.in_place = false,
+            "backup-suffix" => self.backup_suffix = value.unwrap_or_default(),
+            "search-zip" => self.search_zip = true,
+            "no-search-zip" => self.search_zip = false,
+            "index" => self.use_index = true,
+            "no-index" => self.use_index = false,
+            "hidden" => self.hidden = true,
+            "no-hidden" => self.hidden = false,
+            "ignore" => self.use_ignore_files = true,
+            "no-ignore" => self.use_ignore_files = false,
+            "glob" => self.globs.extend(value),
+            "type" => self.types.extend(value),
+            "type-not" => self.types_not.extend(value),
+            "threads" => self.threads = parse_number(name, value)?,
+            // already dealt with by `ConfigFile::load`
+            "no-config" => {}
+            "fuzzy" => self.fuzzy = Some(parse_number(name, value)?),
+            "no-fuzzy" => {
+                self.fuzzy = None;
+                self.transpositions = false;
+            }
+            "transpositions" => self.transpositions = true,
+            "no-transpositions" => self.transpositions = false,
+            "help" => return Err(ArgsError::Help),
+            "version" => return Err(ArgsError::Version),
+            _ => unreachable!("option --{} has no handler", opt.long),
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_number(name: &str, value: Option<String>) -> Result<usize, ArgsError> {
+    let value = value.unwrap_or_default();
+    value
+        .parse()
+        .map_err(|_| ArgsError::InvalidValue(name.to_string(), value))
+}
+
+/// Checks the `args` of a config file, which may only hold options that
+/// set defaults: the query, the patterns and the paths come from the
+/// command line.
+pub(crate) fn check_file_args(args: &[String]) -> Result<(), String> {
+    let mut config = Config::default();
+    let mut positional = Vec::new();
+    let mut patterns_given = false;
+    match config.parse_args(args.iter(), &mut positional, &mut patterns_given) {
+        Ok(()) => {}
+        Err(ArgsError::Help | ArgsError::Version) => {
+            return Err(String::from("args can't ask for help or the version"))
+        }
+        Err(e) => return Err(format!("args: {e}")),
+    }
+
+    if args.iter().any(|arg| arg == "--") {
+        return Err(String::from("args can't include '--'"));
+    }
+    if let Some(arg) = positional.first() {
+        return Err(format!(
+            "args: '{arg}' isn't an option; give the query and paths on the command line"
+        ));
+    }
+    if patterns_given {
+        return Err(String::from(
+            "args can't include -e or -f; give patterns on the command line",
+        ));
+    }
+    Ok(())
+}
+
+/// The command line `minigrep` followed by `args`, for the tests.
+#[cfg(test)]
+pub(crate) fn test_args(args: &[&str]) -> Vec<String> {
+    std::iter::once("minigrep")
+        .chain(args.iter().copied())
+        .map(String::from)
+        .collect()
+}
+
+/// The `Config` for `minigrep` followed by `args`, for the tests.
+#[cfg(test)]
+pub(crate) fn test_config(args: &[&str]) -> Config {
+    Config::build(&test_args(args)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(args: &[&str]) -> Result<Config, ArgsError> {
+        Config::build(&test_args(args))
+    }
+
+    #[test]
+    fn regex_flag_is_parsed() {
+        let config = build(&["-E", "fo+", "poem.txt"]).unwrap();
+
+        assert!(config.regex);
+        assert_eq!(vec!["fo+"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.file_paths);
+    }
+
+    #[test]
+    fn no_follow_flag_is_parsed() {
+        let config = build(&["--no-follow", "frog", "."]).unwrap();
+
+        assert!(!config.follow_links);
+        assert_eq!(vec!["."], config.file_paths);
+    }
+
+    #[test]
+    fn several_paths_are_collected() {
+        let config = build(&["frog", "poem.txt", "-", "src"]).unwrap();
+
+        assert_eq!(vec!["poem.txt", "-", "src"], config.file_paths);
+    }
+
+    #[test]
+    fn missing_path_means_stdin() {
+        let config = build(&["frog"]).unwrap();
+
+        assert_eq!(vec!["-"], config.file_paths);
+    }
+
+    #[test]
+    fn several_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let banned = dir.path().join("banned.txt");
+        fs::write(&banned, "unwrap\n\nexpect\n").unwrap();
+
+        let config = build(&["-e", "todo!", "-f", banned.to_str().unwrap(), "src"]).unwrap();
+
+        assert_eq!(vec!["todo!", "unwrap", "expect"], config.patterns);
+        assert_eq!(vec!["src"], config.file_paths);
+    }
+
+    #[test]
+    fn missing_pattern_file_is_named() {
+        let err = build(&["-f", "no/such/patterns.txt"]).err().unwrap();
+
+        assert!(matches!(err, ArgsError::PatternFile(path, _) if path == "no/such/patterns.txt"));
+    }
+
+    #[test]
+    fn combined_short_flags() {
+        let config = build(&["-nvc", "frog"]).unwrap();
+
+        assert!(config.line_number);
+        assert!(config.invert_match);
+        assert_eq!(OutputMode::Count, config.output);
+    }
+
+    #[test]
+    fn last_output_mode_wins() {
+        let config = build(&["-c", "--files-without-match", "-l", "frog"]).unwrap();
+
+        assert_eq!(OutputMode::FilesWithMatches, config.output);
+
+        let config = build(&["-c", "--json", "frog"]).unwrap();
+
+        assert_eq!(OutputMode::Json, config.output);
+    }
+
+    #[test]
+    fn threads_default_to_the_cpu_count() {
+        assert!(build(&["frog"]).unwrap().threads >= 1);
+        assert_eq!(3, build(&["-j3", "frog"]).unwrap().threads);
+    }
+
+    #[test]
+    fn filters_can_be_repeated() {
+        let config = build(&["-g", "*.rs", "--glob=!*.md", "-trust", "frog"]).unwrap();
+
+        assert_eq!(vec!["*.rs", "!*.md"], config.globs);
+        assert_eq!(vec!["rust"], config.types);
+        assert!(config.use_ignore_files);
+    }
+
+    #[test]
+    fn fuzzy_options() {
+        let config = build(&["--fuzzy=2", "--transpositions", "frog"]).unwrap();
+        assert_eq!(Some(2), config.fuzzy);
+        assert!(config.transpositions);
+
+        assert_eq!(
+            Some(ArgsError::Conflicts(
+                String::from("--fuzzy"),
+                String::from("--regex")
+            )),
+            build(&["--fuzzy", "1", "-E", "fr.g"]).err()
+        );
+        assert!(matches!(
+            build(&["--transpositions", "frog"]),
+            Err(ArgsError::Requires(..))
+        ));
+    }
+
+    #[test]
+    fn multiline_is_line_by_line_elsewhere() {
+        assert!(build(&["-U", "-E", r"fn\s+run", "src"]).unwrap().multiline);
+        assert_eq!(
+            Some(ArgsError::Conflicts(
+                String::from("--multiline"),
+                String::from("--fuzzy")
+            )),
+            build(&["-U", "--fuzzy=1", "frog"]).err()
+        );
+    }
+
+    #[test]
+    fn index_subcommand() {
+        let config = build(&["index", "--hidden", "src"]).unwrap();
+        assert!(config.build_index);
+        assert!(config.hidden);
+        assert_eq!(vec!["src"], config.file_paths);
+
+        assert_eq!(vec!["."], build(&["index"]).unwrap().file_paths);
+        // `minigrep index README.md` most likely meant a search
+        assert_eq!(
+            Err(ArgsError::NotADirectory(String::from("Cargo.toml"))),
+            build(&["index", "Cargo.toml"]).map(|_| ())
+        );
+
+        for args in [
+            &["-e", "index", "src"][..],
+            &["--", "index", "src"],
+            &["-n", "index"],
+        ] {
+            let config = build(args).unwrap();
+            assert!(!config.build_index);
+            assert_eq!(vec!["index"], config.patterns);
+        }
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let file = ConfigFile {
+            args: vec![String::from("--color=never"), String::from("-n")],
+            ignore: vec![String::from("target/")],
+            types: [(String::from("proto"), vec![String::from("*.proto")])].into(),
+            ..ConfigFile::default()
+        };
+        let args = test_args(&["--color=always", "--glob=*.rs", "frog"]);
+        let config = Config::build_with(&args, &file).unwrap();
+
+        assert_eq!(ColorChoice::Always, config.color);
+        assert!(config.line_number);
+        assert_eq!(vec!["!target/", "*.rs"], config.globs);
+        assert_eq!(
+            vec![(String::from("proto"), String::from("*.proto"))],
+            config.type_defs
+        );
+        assert_eq!(vec!["frog"], config.patterns);
+    }
+
+    #[test]
+    fn switches_can_be_turned_back_off() {
+        let file = ConfigFile {
+            args: [
+                "-inwxbvUz",
+                "--json",
+                "--hidden",
+                "--no-ignore",
+                "--no-follow",
+                "--index",
+            ]
+            .map(String::from)
+            .to_vec(),
+            ..ConfigFile::default()
+        };
+        let args = test_args(&[
+            "--case-sensitive",
+            "--no-line-number",
+            "--no-word-regexp",
+            "--no-line-regexp",
+            "--no-byte-offset",
+            "--no-invert-match",
+            "--no-multiline",
+            "--no-search-zip",
+            "--no-json",
+            "--no-hidden",
+            "--ignore",
+            "--follow",
+            "--no-index",
+            "frog",
+        ]);
+        let config = Config::build_with(&args, &file).unwrap();
+        let default = Config::default();
+
+        assert_eq!(default.ignore_case, config.ignore_case);
+        assert_eq!(default.line_number, config.line_number);
+        assert_eq!(default.word_regexp, config.word_regexp);
+        assert_eq!(default.line_regexp, config.line_regexp);
+        assert_eq!(default.byte_offset, config.byte_offset);
+        assert_eq!(default.invert_match, config.invert_match);
+        assert_eq!(default.multiline, config.multiline);
+        assert_eq!(default.search_zip, config.search_zip);
+        assert_eq!(default.output, config.output);
+        assert_eq!(default.hidden, config.hidden);
+        assert_eq!(default.use_ignore_files, config.use_ignore_files);
+        assert_eq!(default.follow_links, config.follow_links);
+        assert_eq!(default.use_index, config.use_index);
+
+        // undoing an option also undoes what only makes sense with it
+        let config = build(&["--fuzzy=1", "--transpositions", "--no-fuzzy", "frog"]).unwrap();
+        assert_eq!((None, false), (config.fuzzy, config.transpositions));
+        let config = build(&["-r", "toad", "--in-place", "--no-replace", "frog"]).unwrap();
+        assert_eq!((None, false), (config.replace, config.in_place));
+        let config = build(&["-S", "-i", "-s", "frog"]).unwrap();
+        assert!(!config.smart_case && !config.ignore_case);
+
+        // an output mode is undone by its own opposite
+        for (mode, opposite) in [
+            ("-c", "--no-count"),
+            ("-l", "--no-files-with-matches"),
+            ("-L", "--no-files-without-match"),
+            ("--json", "--no-json"),
+        ] {
+            let config = build(&[mode, opposite, "frog"]).unwrap();
+            assert_eq!(OutputMode::Lines, config.output, "{mode}");
+        }
+        assert_eq!(
+            OutputMode::Count,
+            build(&["-c", "--no-json", "frog"]).unwrap().output
+        );
+    }
+
+    #[test]
+    fn case_options_are_noted() {
+        assert!(!build(&["frog"]).unwrap().case_chosen);
+        for flag in ["-i", "-S", "-s", "--no-smart-case"] {
+            assert!(build(&[flag, "frog"]).unwrap().case_chosen, "{flag}");
+        }
+    }
+
+    #[test]
+    fn file_options_dont_take_command_line_values() {
+        let file = ConfigFile {
+            args: vec![String::from("--context")],
+            ..ConfigFile::default()
+        };
+        assert_eq!(
+            Err(ArgsError::MissingValue(String::from("--context"))),
+            Config::build_with(&test_args(&["2", "frog"]), &file).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn in_place_needs_a_replacement() {
+        assert_eq!(
+            Some(ArgsError::Requires(
+                String::from("--in-place"),
+                String::from("--replace")
+            )),
+            build(&["--in-place", "run", "src"]).err()
+        );
+
+        let config = build(&["-r", "start", "--in-place", "--backup-suffix=", "run"]).unwrap();
+        assert_eq!(Some(String::from("start")), config.replace);
+        assert_eq!("", config.backup_suffix);
+    }
+
+    #[test]
+    fn color_choice() {
+        assert_eq!(ColorChoice::Auto, build(&["frog"]).unwrap().color);
+        assert_eq!(
+            ColorChoice::Never,
+            build(&["--color=never", "frog"]).unwrap().color
+        );
+        assert_eq!(
+            Some(ArgsError::InvalidValue(
+                String::from("--color"),
+                String::from("sometimes")
+            )),
+            build(&["--color", "sometimes", "frog"]).err()
+        );
+    }
+
+    #[test]
+    fn mmap_choice() {
+        assert_eq!(MmapChoice::Auto, build(&["frog"]).unwrap().mmap);
+        assert_eq!(
+            MmapChoice::Never,
+            build(&["--mmap=never", "frog"]).unwrap().mmap
+        );
+        assert!(build(&["--mmap=sometimes", "frog"]).is_err());
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let config = build(&["--ignore-case", "--", "-v", "--count"]).unwrap();
+
+        assert!(config.ignore_case);
+        assert!(!config.invert_match);
+        assert_eq!(vec!["-v"], config.patterns);
+        assert_eq!(vec!["--count"], config.file_paths);
+    }
+
+    #[test]
+    fn context_values() {
+        let config = build(&["-C", "2", "-A1", "--before-context=3", "frog"]).unwrap();
+
+        assert_eq!(3, config.before_context);
+        assert_eq!(1, config.after_context);
+        assert_eq!(vec!["frog"], config.patterns);
+    }
+
+    #[test]
+    fn errors_name_the_bad_argument() {
+        assert_eq!(
+            Some(ArgsError::UnknownOption(String::from("-y"))),
+            build(&["-iy", "frog"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::UnknownOption(String::from("--colour"))),
+            build(&["--colour", "frog"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::UnexpectedValue(String::from("--count"))),
+            build(&["--count=3", "frog"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::MissingValue(String::from("-A"))),
+            build(&["frog", "-nA"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::InvalidValue(
+                String::from("--context"),
+                String::from("lots")
+            )),
+            build(&["--context=lots", "frog"]).err()
+        );
+        assert_eq!(Some(ArgsError::Help), build(&["-h"]).err());
+    }
+}
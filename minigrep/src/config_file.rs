@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::color::{self, Colors};
+use crate::config;
+use crate::ArgsError;
+
+/// Defaults for every search, read from `~/.config/minigrep/config.toml`
+/// or the file `MINIGREP_CONFIG` names:
+///
+/// ```toml
+/// # options, applied before the command line's own
+/// args = ["--smart-case", "--hidden"]
+/// # globs for files never to search
+/// ignore = ["target/", "*.min.js"]
+///
+/// [colors]
+/// path = "magenta"
+/// number = "green"
+/// separator = "cyan"
+/// match = "bold red"
+///
+/// # file types for --type, on top of the built-in ones
+/// [types]
+/// proto = ["*.proto"]
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    pub args: Vec<String>,
+    pub ignore: Vec<String>,
+    pub colors: Colors,
+    pub types: BTreeMap<String, Vec<String>>,
+}
+
+/// The file as written, before the colours are checked.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Raw {
+    args: Vec<String>,
+    ignore: Vec<String>,
+    colors: RawColors,
+    types: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawColors {
+    path: Option<String>,
+    number: Option<String>,
+    separator: Option<String>,
+    #[serde(rename = "match")]
+    matched: Option<String>,
+}
+
+impl ConfigFile {
+    /// Reads the config file, unless `args` (the whole command line)
+    /// includes `--no-config` or `MINIGREP_CONFIG` is set but empty.
+    ///
+    /// The default file not existing just means there are no defaults, but
+    /// one named by `MINIGREP_CONFIG` has to be there.
+    pub fn load(args: &[String]) -> Result<ConfigFile, ArgsError> {
+        let no_config = args
+            .iter()
+            .skip(1)
+            .take_while(|arg| *arg != "--")
+            .any(|arg| arg == "--no-config");
+        if no_config {
+            return Ok(ConfigFile::default());
+        }
+
+        let (path, required) = match env::var_os("MINIGREP_CONFIG") {
+            Some(path) if path.is_empty() => return Ok(ConfigFile::default()),
+            Some(path) => (PathBuf::from(path), true),
+            None => match dirs::home_dir() {
+                Some(home) => (home.join(".config/minigrep/config.toml"), false),
+                None => return Ok(ConfigFile::default()),
+            },
+        };
+
+        let error = |reason: String| ArgsError::ConfigFile(path.display().to_string(), reason);
+        match fs::read_to_string(&path) {
+            Ok(text) => ConfigFile::parse(&text).map_err(error),
+            Err(e) if !required && e.kind() == io::ErrorKind::NotFound => Ok(ConfigFile::default()),
+            Err(e) => Err(error(e.to_string())),
+        }
+    }
+
+    /// Parses the contents of a config file; unknown keys are an error, so
+    /// a misspelt setting doesn't go unnoticed, and so is anything in `args`
+    /// that isn't an option.
+    pub fn parse(text: &str) -> Result<ConfigFile, String> {
+        let raw: Raw = toml::from_str(text).map_err(|e| e.to_string())?;
+        config::check_file_args(&raw.args)?;
+
+        let mut colors = Colors::default();
+        for (name, style, color) in [
+            ("path", raw.colors.path, &mut colors.path),
+            ("number", raw.colors.number, &mut colors.number),
+            ("separator", raw.colors.separator, &mut colors.separator),
+            ("match", raw.colors.matched, &mut colors.matched),
+        ] {
+            if let Some(style) = style {
+                *color = color::parse_style(&style)
+                    .ok_or_else(|| format!("invalid style '{style}' for colors.{name}"))?;
+            }
+        }
+
+        Ok(ConfigFile {
+            args: raw.args,
+            ignore: raw.ignore,
+            colors,
+            types: raw.types,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_args;
+
+    #[test]
+    fn every_setting_is_read() {
+        let file = ConfigFile::parse(
+            "args = ['--smart-case']\n\
+             ignore = ['target/']\n\
+             [colors]\n\
+             match = 'underline green'\n\
+             [types]\n\
+             proto = ['*.proto']\n",
+        )
+        .unwrap();
+
+        assert_eq!(vec!["--smart-case"], file.args);
+        assert_eq!(vec!["target/"], file.ignore);
+        assert_eq!("\x1b[4;32m", file.colors.matched);
+        assert_eq!(Colors::default().path, file.colors.path);
+        assert_eq!(vec!["*.proto"], file.types["proto"]);
+    }
+
+    #[test]
+    fn mistakes_are_errors() {
+        assert!(ConfigFile::parse("arg = ['--hidden']").is_err());
+        assert!(ConfigFile::parse("[colors]\npath = 'mauve'").is_err());
+        assert!(ConfigFile::parse("args = '--hidden'").is_err());
+    }
+
+    #[test]
+    fn args_must_be_options() {
+        let error = |args: &str| ConfigFile::parse(&format!("args = {args}")).unwrap_err();
+
+        assert_eq!("args: unrecognized option '--hiden'", error("['--hiden']"));
+        assert_eq!("args: option '-A' requires an argument", error("['-A']"));
+        assert!(error("['--hidden', 'frog']").contains("'frog' isn't an option"));
+        assert_eq!("args can't include '--'", error("['--', 'frog']"));
+        assert!(error("['-e', 'frog']").contains("-e or -f"));
+        assert!(error("['--help']").contains("help"));
+        assert!(ConfigFile::parse("args = ['-S', '--context=2', '--no-hidden']").is_ok());
+    }
+
+    #[test]
+    fn no_config_skips_the_file() {
+        let args = test_args(&["--no-config", "frog"]);
+
+        assert!(ConfigFile::load(&args).unwrap().args.is_empty());
+    }
+}
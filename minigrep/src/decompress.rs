@@ -0,0 +1,101 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+/// The compression formats `-z` knows, by the magic bytes their files
+/// start with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+const MAGIC: [(&[u8], Format); 4] = [
+    (b"\x1f\x8b", Format::Gzip),
+    (b"BZh", Format::Bzip2),
+    (b"\xfd7zXZ\x00", Format::Xz),
+    (b"\x28\xb5\x2f\xfd", Format::Zstd),
+];
+
+/// Whether `start`, the beginning of a file, looks compressed.
+pub(crate) fn is_compressed(start: &[u8]) -> bool {
+    MAGIC.iter().any(|(magic, _)| start.starts_with(magic))
+}
+
+/// Wraps `reader` in a decoder if it starts like a compressed file, and
+/// hands it back as it is otherwise. Going by content rather than by file
+/// name means rotated logs like `app.log.1` are handled too.
+///
+/// Files made of several compressed streams one after another, as
+/// `cat a.gz b.gz` or parallel compressors produce, are read to the end.
+pub(crate) fn reader<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let start = reader.fill_buf()?;
+    let format = MAGIC
+        .iter()
+        .find(|(magic, _)| start.starts_with(magic))
+        .map(|&(_, format)| format);
+
+    let decoder: Box<dyn Read + 'a> = match format {
+        None => return Ok(Box::new(reader)),
+        Some(Format::Gzip) => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        Some(Format::Bzip2) => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+        Some(Format::Xz) => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        Some(Format::Zstd) => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+    };
+
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const POEM: &str = "I'm nobody! Who are you?\nHow public, like a frog\n";
+
+    fn read_all(compressed: &[u8]) -> String {
+        let mut text = String::new();
+        reader(compressed)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn plain_text_is_left_alone() {
+        assert_eq!(POEM, read_all(POEM.as_bytes()));
+    }
+
+    #[test]
+    fn decompresses_each_format() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(POEM.as_bytes()).unwrap();
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(POEM.as_bytes()).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(POEM.as_bytes()).unwrap();
+
+        assert_eq!(POEM, read_all(&gzip.finish().unwrap()));
+        assert_eq!(POEM, read_all(&bzip2.finish().unwrap()));
+        assert_eq!(POEM, read_all(&xz.finish().unwrap()));
+        assert_eq!(
+            POEM,
+            read_all(&zstd::encode_all(POEM.as_bytes(), 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn concatenated_streams_are_read_to_the_end() {
+        let mut gzip = Vec::new();
+        for half in ["I'm nobody! Who are you?\n", "How public, like a frog\n"] {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(half.as_bytes()).unwrap();
+            gzip.extend(encoder.finish().unwrap());
+        }
+
+        assert_eq!(POEM, read_all(&gzip));
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::ArgsError;
+
+/// Everything that can go wrong between parsing the command line and
+/// printing the last result.
+///
+/// Variants that concern a particular file carry its path, and I/O errors
+/// keep the original `io::Error` so callers can tell e.g. a missing file
+/// from one they aren't allowed to read by its `kind()`.
+#[derive(Debug)]
+pub enum Error {
+    /// The command line didn't make sense.
+    Args(ArgsError),
+    /// A `--regex` pattern isn't a valid regular expression.
+    Regex(regex::Error),
+    /// The literal patterns couldn't be compiled, e.g. there are too many.
+    Patterns(aho_corasick::BuildError),
+    /// A `--glob` or `--type` filter is invalid for the directory at `path`.
+    Walk {
+        path: PathBuf,
+        source: ignore::Error,
+    },
+    /// An entry below a directory being searched couldn't be read; the
+    /// error names it.
+    Entry(ignore::Error),
+    /// Reading, or with `--in-place` rewriting, `path` failed.
+    Io { path: PathBuf, source: io::Error },
+    /// Writing the results failed.
+    Output(io::Error),
+    /// `--in-place` was given standard input, which has nowhere to go back to.
+    InPlaceStdin,
+    /// `--index` found no index covering the directory at this path.
+    NoIndex(PathBuf),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Args(e) => write!(f, "{e}"),
+            Error::Regex(e) => write!(f, "invalid regex: {e}"),
+            Error::Patterns(e) => write!(f, "cannot compile patterns: {e}"),
+            Error::Walk { path, source } => write!(f, "{}: {source}", path.display()),
+            Error::Entry(e) => write!(f, "{e}"),
+            Error::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            Error::Output(e) => write!(f, "cannot write output: {e}"),
+            Error::InPlaceStdin => write!(f, "standard input can't be edited in place"),
+            Error::NoIndex(path) => write!(
+                f,
+                "{}: no index found, run `minigrep index` to build one",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Args(e) => Some(e),
+            Error::Regex(e) => Some(e),
+            Error::Patterns(e) => Some(e),
+            Error::Walk { source, .. } | Error::Entry(source) => Some(source),
+            Error::Io { source, .. } | Error::Output(source) => Some(source),
+            Error::InPlaceStdin | Error::NoIndex(_) => None,
+        }
+    }
+}
+
+impl From<ArgsError> for Error {
+    fn from(e: ArgsError) -> Error {
+        Error::Args(e)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Error {
+        Error::Regex(e)
+    }
+}
+
+impl From<aho_corasick::BuildError> for Error {
+    fn from(e: aho_corasick::BuildError) -> Error {
+        Error::Patterns(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_name_the_file() {
+        let err = Error::Io {
+            path: PathBuf::from("poem.txt"),
+            source: io::Error::from(io::ErrorKind::PermissionDenied),
+        };
+
+        assert!(err.to_string().starts_with("poem.txt: "));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+}
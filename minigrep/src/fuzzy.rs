@@ -0,0 +1,210 @@
+use std::cmp::Reverse;
+use std::mem;
+use std::ops::Range;
+
+use crate::search::{fold_case, fold_case_with_origins, is_word_char, original_end, Anchor};
+
+/// A query to look for approximately, allowing up to `max_edits` inserted,
+/// deleted or substituted characters, and with `transpositions` also
+/// swapped neighbours, each counting as one edit. With an `anchor`, only
+/// matches that form whole words or the whole line count.
+#[derive(Debug)]
+pub(crate) struct Fuzzy {
+    query: Vec<char>,
+    max_edits: usize,
+    transpositions: bool,
+    ignore_case: bool,
+    anchor: Anchor,
+}
+
+impl Fuzzy {
+    pub(crate) fn new(
+        query: &str,
+        max_edits: usize,
+        transpositions: bool,
+        ignore_case: bool,
+        anchor: Anchor,
+    ) -> Fuzzy {
+        let query = if ignore_case {
+            fold_case(query)
+        } else {
+            query.to_string()
+        };
+
+        Fuzzy {
+            query: query.chars().collect(),
+            max_edits,
+            transpositions,
+            ignore_case,
+            anchor,
+        }
+    }
+
+    /// The closest approximate occurrence of the query in `line`, as its
+    /// edit distance and byte range, if it is within `max_edits`.
+    ///
+    /// This is Sellers' variant of the Levenshtein distance: the match may
+    /// start anywhere in the line at no cost, so the result is the distance
+    /// to the best-fitting substring rather than to the whole line. Among
+    /// equally close matches the leftmost, then the longest, wins.
+    ///
+    /// The anchor narrows where a match may start and end: at word
+    /// boundaries for `Word`, and only at the ends of the line for `Line`.
+    /// That is part of the search, so a match that fits is found even when
+    /// a closer one that doesn't is also there.
+    ///
+    /// Ignoring case works as everywhere else: both sides are case folded,
+    /// and the range is mapped back to the original line.
+    pub(crate) fn find(&self, line: &str) -> Option<(usize, Range<usize>)> {
+        if !self.ignore_case {
+            return self.find_exact(line);
+        }
+
+        let (folded, origins) = fold_case_with_origins(line);
+        let (distance, span) = self.find_exact(&folded)?;
+        let end = original_end(line, &origins, span.start, span.end);
+        Some((distance, origins[span.start]..end))
+    }
+
+    fn find_exact(&self, line: &str) -> Option<(usize, Range<usize>)> {
+        let text: Vec<(usize, char)> = line.char_indices().collect();
+        let (m, n) = (self.query.len(), text.len());
+
+        // row i holds, for each j, the fewest edits turning query[..i] into
+        // some text ending at character j, and where that text begins. Only
+        // the last row is needed in the end, so just the rows the next one
+        // is computed from are kept: the previous one, and with
+        // transpositions the one before it.
+        let can_start = |j: usize| match self.anchor {
+            Anchor::Anywhere => true,
+            Anchor::Word => j == 0 || !is_word_char(text[j - 1].1),
+            Anchor::Line => j == 0,
+        };
+        let can_end = |j: usize| match self.anchor {
+            Anchor::Anywhere => true,
+            Anchor::Word => j == n || !is_word_char(text[j].1),
+            Anchor::Line => j == n,
+        };
+
+        // the empty query fits for free wherever a match may start;
+        // anywhere else it has to take in the characters since the last
+        // such place
+        let mut previous: Vec<(usize, usize)> = Vec::with_capacity(n + 1);
+        for j in 0..=n {
+            let entry = match previous.last() {
+                Some(&(distance, start)) if !can_start(j) => (distance + 1, start),
+                _ => (0, j),
+            };
+            previous.push(entry);
+        }
+        let mut two_back = vec![(0, 0); n + 1];
+        let mut current = vec![(0, 0); n + 1];
+
+        for i in 1..=m {
+            current[0] = (i, 0);
+            for j in 1..=n {
+                let cost = usize::from(self.query[i - 1] != text[j - 1].1);
+                let (diagonal, start) = previous[j - 1];
+                let mut best = (diagonal + cost, start);
+                // a query character the text doesn't have
+                if previous[j].0 + 1 < best.0 {
+                    best = (previous[j].0 + 1, previous[j].1);
+                }
+                // a text character the query doesn't have
+                if current[j - 1].0 + 1 < best.0 {
+                    best = (current[j - 1].0 + 1, current[j - 1].1);
+                }
+                if self.transpositions
+                    && i > 1
+                    && j > 1
+                    && self.query[i - 1] == text[j - 2].1
+                    && self.query[i - 2] == text[j - 1].1
+                    && two_back[j - 2].0 + 1 < best.0
+                {
+                    best = (two_back[j - 2].0 + 1, two_back[j - 2].1);
+                }
+                current[j] = best;
+            }
+
+            mem::swap(&mut two_back, &mut previous);
+            mem::swap(&mut previous, &mut current);
+        }
+
+        let last = previous;
+        let end = (0..=n)
+            .filter(|&j| can_end(j))
+            .min_by_key(|&j| (last[j].0, last[j].1, Reverse(j)))?;
+        let (distance, start) = last[end];
+        if distance > self.max_edits {
+            return None;
+        }
+
+        let byte = |j: usize| text.get(j).map_or(line.len(), |&(offset, _)| offset);
+        Some((distance, byte(start)..byte(end)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_match_is_distance_zero() {
+        let fuzzy = Fuzzy::new("frog", 1, false, false, Anchor::Anywhere);
+
+        assert_eq!(Some((0, 19..23)), fuzzy.find("How public, like a frog"));
+    }
+
+    #[test]
+    fn finds_typos_within_the_limit() {
+        let fuzzy = Fuzzy::new("frog", 1, false, false, Anchor::Anywhere);
+
+        assert_eq!(Some((1, 2..5)), fuzzy.find("a frg"));
+        assert_eq!(Some((1, 2..6)), fuzzy.find("a frig"));
+        assert_eq!(None, fuzzy.find("a fr"));
+    }
+
+    #[test]
+    fn transpositions_count_once() {
+        let line = "a forg";
+
+        assert_eq!(
+            None,
+            Fuzzy::new("frog", 1, false, false, Anchor::Anywhere).find(line)
+        );
+        assert_eq!(
+            Some((1, 2..6)),
+            Fuzzy::new("frog", 1, true, false, Anchor::Anywhere).find(line)
+        );
+    }
+
+    #[test]
+    fn anchored_matches_take_in_the_rest() {
+        let word = |line| Fuzzy::new("frog", 1, false, false, Anchor::Word).find(line);
+        let whole = |line| Fuzzy::new("frog", 1, false, false, Anchor::Line).find(line);
+
+        assert_eq!(Some((1, 0..5)), whole("frogs"));
+        assert_eq!(None, whole("frogs!"));
+        assert_eq!(Some((1, 0..5)), word("frogs are"));
+        assert_eq!(Some((0, 8..12)), word("frogsss frog"));
+        assert_eq!(None, word("leapfrogging"));
+    }
+
+    #[test]
+    fn ranges_are_in_bytes() {
+        let fuzzy = Fuzzy::new("cafe", 1, false, true, Anchor::Anywhere);
+
+        assert_eq!(Some((1, 3..8)), fuzzy.find("un CAFÉ noir"));
+    }
+
+    #[test]
+    fn ignoring_case_folds_fully() {
+        let fuzzy = Fuzzy::new("strasse", 0, false, true, Anchor::Anywhere);
+
+        assert_eq!(Some((0, 4..11)), fuzzy.find("die STRAßE"));
+        assert_eq!(
+            None,
+            Fuzzy::new("strasse", 0, false, false, Anchor::Anywhere).find("die STRAßE")
+        );
+    }
+}
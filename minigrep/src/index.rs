@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use tempfile::NamedTempFile;
+
+use crate::search::fold_case;
+use crate::{decompress, walk, Config, Error, OutputMode};
+
+/// The index of a tree lives in this file at its root.
+pub(crate) const FILE_NAME: &str = ".minigrep-index";
+
+// changed whenever the layout does, so an old index is rebuilt, not misread
+const MAGIC: &[u8] = b"minigrep-index 1\n";
+
+const COMPRESSED: u8 = 1;
+const BINARY: u8 = 2;
+
+/// What the index knows about one file.
+struct Entry {
+    /// Modification time as seconds and nanoseconds since the epoch; with
+    /// `size`, how we tell the file has changed since it was indexed.
+    modified: (u64, u32),
+    size: u64,
+    flags: u8,
+    /// Every three-byte sequence in the case-folded contents, sorted and
+    /// stored as varint-encoded deltas. They're only decoded while checking
+    /// a file, which keeps a large index small in memory as well as on disk.
+    trigrams: Vec<u8>,
+}
+
+/// A trigram index of the files below `root`, keyed by their path relative
+/// to it.
+struct Index {
+    root: PathBuf,
+    files: HashMap<String, Entry>,
+}
+
+/// What `update` did.
+pub(crate) struct Summary {
+    pub(crate) files: usize,
+    pub(crate) updated: usize,
+    pub(crate) removed: usize,
+    /// Files that couldn't be indexed; they are left out.
+    pub(crate) errors: Vec<Error>,
+}
+
+/// Builds the index of `root`, or brings an existing one up to date.
+///
+/// Files whose modification time and size haven't changed since they were
+/// last indexed are not read again, so refreshing the index of a large tree
+/// after a few edits is quick. The files indexed are the ones `--glob`,
+/// `--type`, `--hidden` and the ignore files would have a search look at.
+pub(crate) fn update(root: &Path, config: &Config) -> Result<Summary, Error> {
+    if !root.is_dir() {
+        return Err(Error::Io {
+            path: root.to_path_buf(),
+            source: io::Error::from(io::ErrorKind::NotADirectory),
+        });
+    }
+    let mut old = load(root).unwrap_or_default();
+    let files = walk::files(root, config).map_err(|source| Error::Walk {
+        path: root.to_path_buf(),
+        source,
+    })?;
+
+    let mut index = Index {
+        root: root.to_path_buf(),
+        files: HashMap::new(),
+    };
+    let mut updated = 0;
+    let mut errors: Vec<Error> = files.errors.into_iter().map(Error::Entry).collect();
+
+    for path in files.paths {
+        let Some(key) = path.strip_prefix(root).ok().and_then(key) else {
+            continue;
+        };
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(source) => {
+                errors.push(Error::Io { path, source });
+                continue;
+            }
+        };
+
+        let entry = match old.remove(&key) {
+            Some(entry) if entry.is_fresh(&metadata) => entry,
+            _ => match read_entry(&path, &metadata) {
+                Ok(entry) => {
+                    updated += 1;
+                    entry
+                }
+                Err(source) => {
+                    errors.push(Error::Io { path, source });
+                    continue;
+                }
+            },
+        };
+        index.files.insert(key, entry);
+    }
+
+    index.save().map_err(|source| Error::Io {
+        path: root.join(FILE_NAME),
+        source,
+    })?;
+
+    Ok(Summary {
+        files: index.files.len(),
+        updated,
+        removed: old.len(),
+        errors,
+    })
+}
+
+/// Decides, from the index, which files in a directory can be left out of
+/// a search.
+pub(crate) struct Filter {
+    index: Index,
+    /// The directory being searched as it was given, and where it is
+    /// relative to the index root.
+    dir: PathBuf,
+    prefix: PathBuf,
+    /// The trigrams each pattern needs; a file must have all of one set.
+    required: Vec<Vec<u32>>,
+    search_zip: bool,
+}
+
+impl Filter {
+    /// Looks for the index covering `dir` there or in a parent directory.
+    ///
+    /// Returns `None`, meaning every file has to be searched, when the
+    /// search is one the index can't narrow down, like a regex or one that
+    /// also prints files without matches. Not finding a usable index is an
+    /// error, as `--index` was asked for, but the search can go on without.
+    pub(crate) fn new(dir: &Path, config: &Config) -> Result<Option<Filter>, Error> {
+        let Some(required) = required_trigrams(config) else {
+            return Ok(None);
+        };
+
+        let canonical = dir.canonicalize().map_err(|source| Error::Io {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        let root = canonical
+            .ancestors()
+            .find(|root| root.join(FILE_NAME).is_file())
+            .ok_or_else(|| Error::NoIndex(dir.to_path_buf()))?;
+
+        let files = load(root).map_err(|source| Error::Io {
+            path: root.join(FILE_NAME),
+            source,
+        })?;
+
+        Ok(Some(Filter {
+            prefix: canonical
+                .strip_prefix(root)
+                .map_or_else(|_| PathBuf::new(), Path::to_path_buf),
+            index: Index {
+                root: root.to_path_buf(),
+                files,
+            },
+            dir: dir.to_path_buf(),
+            required,
+            search_zip: config.search_zip,
+        }))
+    }
+
+    /// Whether `path`, found while walking the directory, might contain a
+    /// match. Files that are new or have changed since they were indexed
+    /// might, as far as we know.
+    pub(crate) fn may_match(&self, path: &Path) -> bool {
+        let entry = path
+            .strip_prefix(&self.dir)
+            .ok()
+            .and_then(|relative| key(&self.prefix.join(relative)))
+            .and_then(|key| self.index.files.get(&key));
+        let Some(entry) = entry else {
+            return true;
+        };
+        if !fs::metadata(path).is_ok_and(|metadata| entry.is_fresh(&metadata)) {
+            return true;
+        }
+
+        // without -z a compressed file is searched as it is, not as indexed
+        if entry.flags & COMPRESSED != 0 && !self.search_zip {
+            return true;
+        }
+        // walked binary files are skipped anyway
+        if entry.flags & BINARY != 0 {
+            return false;
+        }
+
+        self.required
+            .iter()
+            .any(|required| contains_all(&entry.trigrams, required))
+    }
+}
+
+/// The trigrams a file needs for each pattern to be able to match it, or
+/// `None` if skipping files that lack them could change the output.
+fn required_trigrams(config: &Config) -> Option<Vec<Vec<u32>>> {
+    if config.regex || config.fuzzy.is_some() || config.invert_match {
+        return None;
+    }
+    // these print something for files without matches too
+    if !matches!(
+        config.output,
+        OutputMode::Lines | OutputMode::FilesWithMatches
+    ) {
+        return None;
+    }
+
+    config
+        .patterns
+        .iter()
+        .map(|pattern| {
+            let trigrams = trigrams(fold_case(pattern).as_bytes());
+            // a short pattern could be anywhere, so nothing can be skipped
+            (!trigrams.is_empty()).then_some(trigrams)
+        })
+        .collect()
+}
+
+impl Entry {
+    fn is_fresh(&self, metadata: &Metadata) -> bool {
+        self.modified == modified(metadata) && self.size == metadata.len()
+    }
+}
+
+fn read_entry(path: &Path, metadata: &Metadata) -> io::Result<Entry> {
+    let mut contents = fs::read(path)?;
+    let mut flags = 0;
+
+    if decompress::is_compressed(&contents) {
+        flags |= COMPRESSED;
+        let mut plain = Vec::new();
+        decompress::reader(&contents[..])?.read_to_end(&mut plain)?;
+        contents = plain;
+    }
+
+    let trigrams = if walk::is_binary(&contents) {
+        flags |= BINARY;
+        Vec::new()
+    } else {
+        let text = fold_case(&String::from_utf8_lossy(&contents));
+        encode(&trigrams(text.as_bytes()))
+    };
+
+    Ok(Entry {
+        modified: modified(metadata),
+        size: metadata.len(),
+        flags,
+        trigrams,
+    })
+}
+
+fn modified(metadata: &Metadata) -> (u64, u32) {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or((0, 0), |since| (since.as_secs(), since.subsec_nanos()))
+}
+
+/// How the index names the file at `relative` to its root.
+fn key(relative: &Path) -> Option<String> {
+    let parts: Option<Vec<&str>> = relative.iter().map(|part| part.to_str()).collect();
+    Some(parts?.join("/"))
+}
+
+/// Every distinct three-byte sequence in `bytes`, sorted.
+fn trigrams(bytes: &[u8]) -> Vec<u32> {
+    let mut trigrams: Vec<u32> = bytes
+        .windows(3)
+        .map(|w| u32::from_be_bytes([0, w[0], w[1], w[2]]))
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+/// Stores sorted trigrams as the differences between neighbours, seven bits
+/// to a byte; neighbours are close, so most take one or two bytes.
+fn encode(trigrams: &[u32]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut previous = 0;
+
+    for &trigram in trigrams {
+        let mut delta = trigram - previous;
+        while delta >= 0x80 {
+            encoded.push(delta as u8 | 0x80);
+            delta >>= 7;
+        }
+        encoded.push(delta as u8);
+        previous = trigram;
+    }
+
+    encoded
+}
+
+fn decode(encoded: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    let mut bytes = encoded.iter();
+    let mut previous = 0;
+
+    std::iter::from_fn(move || {
+        let (mut delta, mut shift) = (0, 0);
+        loop {
+            let byte = *bytes.next()?;
+            delta |= u32::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        previous += delta;
+        Some(previous)
+    })
+}
+
+/// Whether the encoded trigrams include every one of `required`, which is
+/// sorted too, so a single pass over both will do.
+fn contains_all(encoded: &[u8], required: &[u32]) -> bool {
+    let mut required = required.iter().peekable();
+
+    for trigram in decode(encoded) {
+        if required.peek().is_some_and(|&&r| r < trigram) {
+            return false;
+        }
+        required.next_if(|&&r| r == trigram);
+        if required.peek().is_none() {
+            return true;
+        }
+    }
+
+    required.peek().is_none()
+}
+
+/// Reads the index at the root of `root`; a missing or unreadable one is
+/// an error, which `update` answers by indexing everything again.
+fn load(root: &Path) -> io::Result<HashMap<String, Entry>> {
+    let data = fs::read(root.join(FILE_NAME))?;
+    let mut data = data
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a minigrep index"))?;
+    let mut files = HashMap::new();
+
+    while !data.is_empty() {
+        let key = read_bytes(&mut data)?;
+        let key =
+            String::from_utf8(key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let modified = (read_u64(&mut data)?, read_u32(&mut data)?);
+        let size = read_u64(&mut data)?;
+        let mut flags = [0];
+        data.read_exact(&mut flags)?;
+        let trigrams = read_bytes(&mut data)?;
+
+        files.insert(
+            key,
+            Entry {
+                modified,
+                size,
+                flags: flags[0],
+                trigrams,
+            },
+        );
+    }
+
+    Ok(files)
+}
+
+impl Index {
+    /// Writes the index next to the files it covers. It goes to a temporary
+    /// file first, so a search running meanwhile sees the old index or the
+    /// new one, never half of one.
+    fn save(&self) -> io::Result<()> {
+        let mut keys: Vec<&String> = self.files.keys().collect();
+        keys.sort();
+
+        let mut out = io::BufWriter::new(NamedTempFile::new_in(&self.root)?);
+        out.write_all(MAGIC)?;
+        for key in keys {
+            let entry = &self.files[key];
+            write_bytes(&mut out, key.as_bytes())?;
+            out.write_all(&entry.modified.0.to_le_bytes())?;
+            out.write_all(&entry.modified.1.to_le_bytes())?;
+            out.write_all(&entry.size.to_le_bytes())?;
+            out.write_all(&[entry.flags])?;
+            write_bytes(&mut out, &entry.trigrams)?;
+        }
+
+        let file = out.into_inner().map_err(io::IntoInnerError::into_error)?;
+        file.persist(self.root.join(FILE_NAME))?;
+        Ok(())
+    }
+}
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    let len =
+        u32::try_from(bytes.len()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(bytes)
+}
+
+fn read_bytes(data: &mut &[u8]) -> io::Result<Vec<u8>> {
+    // a damaged length mustn't make us allocate more than there is to read
+    let len = read_u32(data)? as usize;
+    if len > data.len() {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    let mut bytes = vec![0; len];
+    data.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(data: &mut &[u8]) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    data.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(data: &mut &[u8]) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    data.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    #[test]
+    fn encoded_trigrams_round_trip() {
+        let trigrams = trigrams(b"how public, like a frog");
+
+        assert_eq!(trigrams, decode(&encode(&trigrams)).collect::<Vec<_>>());
+        assert!(contains_all(&encode(&trigrams), &super::trigrams(b"frog")));
+        assert!(!contains_all(&encode(&trigrams), &super::trigrams(b"toad")));
+    }
+
+    #[test]
+    fn updates_only_what_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("frog.txt"), "How public, like a frog\n").unwrap();
+        fs::write(dir.path().join("bog.txt"), "To an admiring bog!\n").unwrap();
+        let config = test_config(&["index"]);
+
+        let summary = update(dir.path(), &config).unwrap();
+        assert_eq!((2, 2, 0), (summary.files, summary.updated, summary.removed));
+
+        fs::write(dir.path().join("frog.txt"), "How dreary to be somebody!\n").unwrap();
+        fs::remove_file(dir.path().join("bog.txt")).unwrap();
+        let summary = update(dir.path(), &config).unwrap();
+        assert_eq!((1, 1, 1), (summary.files, summary.updated, summary.removed));
+    }
+
+    #[test]
+    fn skips_files_that_cannot_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let frog = dir.path().join("frog.txt");
+        let bog = dir.path().join("bog.txt");
+        fs::write(&frog, "How public, like a FROG\n").unwrap();
+        fs::write(&bog, "To an admiring bog!\n").unwrap();
+        update(dir.path(), &test_config(&["index"])).unwrap();
+
+        let filter = Filter::new(dir.path(), &test_config(&["--index", "frog"]))
+            .unwrap()
+            .unwrap();
+        assert!(filter.may_match(&frog));
+        assert!(!filter.may_match(&bog));
+
+        // a file changed since indexing has to be searched
+        fs::write(&bog, "To tell your name, frog, the livelong day\n").unwrap();
+        assert!(filter.may_match(&bog));
+
+        assert!(
+            Filter::new(dir.path(), &test_config(&["--index", "-E", "fr.g"]))
+                .unwrap()
+                .is_none()
+        );
+        assert!(Filter::new(dir.path(), &test_config(&["--index", "fr"]))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn a_missing_or_damaged_index_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(&["--index", "frog"]);
+        assert!(matches!(
+            Filter::new(dir.path(), &config),
+            Err(Error::NoIndex(_))
+        ));
+
+        // a length far past the end of the file
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(dir.path().join(FILE_NAME), data).unwrap();
+        let Err(Error::Io { source, .. }) = Filter::new(dir.path(), &config) else {
+            panic!("a damaged index was accepted");
+        };
+        assert_eq!(io::ErrorKind::UnexpectedEof, source.kind());
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs::{File, Metadata};
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::{decompress, Config, MmapChoice};
+
+// below this, setting up the mapping costs more than the reads it saves
+const MMAP_MIN_LEN: u64 = 1 << 20;
+
+/// Opens `path` for searching.
+///
+/// Large files are memory-mapped so the search runs over the page cache
+/// directly instead of copying the file through a buffer a few kilobytes
+/// at a time; `--mmap` can force this either way. Anything that can't be
+/// mapped, like a pipe or an empty file, is read normally. With `-z` the
+/// contents are decompressed first.
+pub(crate) fn open(path: &Path, config: &Config) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+
+    let reader: Box<dyn BufRead> = match map(&file, &metadata, config.mmap) {
+        Some(map) => Box::new(Cursor::new(map)),
+        None => Box::new(BufReader::new(file)),
+    };
+
+    if config.search_zip {
+        decompress::reader(reader)
+    } else {
+        Ok(reader)
+    }
+}
+
+fn map(file: &File, metadata: &Metadata, choice: MmapChoice) -> Option<Mmap> {
+    let wanted = match choice {
+        MmapChoice::Auto => metadata.len() >= MMAP_MIN_LEN,
+        MmapChoice::Always => metadata.len() > 0,
+        MmapChoice::Never => false,
+    };
+    if !wanted || !metadata.is_file() {
+        return None;
+    }
+
+    // SAFETY: the map is only ever read. If another process truncates the
+    // file while we search it, reading the lost pages raises SIGBUS; grep
+    // tools that mmap accept the same risk, and `--mmap=never` avoids it.
+    unsafe { Mmap::map(file) }.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+
+    use super::*;
+    use crate::config::test_config;
+
+    #[test]
+    fn mapped_and_read_files_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        fs::write(&path, "I'm nobody! Who are you?\nHow public, like a frog\n").unwrap();
+
+        let mut mapped = String::new();
+        open(&path, &test_config(&["--mmap=always", "frog"]))
+            .unwrap()
+            .read_to_string(&mut mapped)
+            .unwrap();
+        let mut read = String::new();
+        open(&path, &test_config(&["--mmap=never", "frog"]))
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+
+        assert_eq!(read, mapped);
+    }
+
+    #[test]
+    fn only_large_files_are_mapped_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = dir.path().join("poem.txt");
+        let empty = dir.path().join("empty.txt");
+        fs::write(&small, "How public, like a frog\n").unwrap();
+        fs::write(&empty, "").unwrap();
+
+        let mapped = |path: &Path, choice| {
+            let file = File::open(path).unwrap();
+            map(&file, &file.metadata().unwrap(), choice).is_some()
+        };
+
+        assert!(!mapped(&small, MmapChoice::Auto));
+        assert!(mapped(&small, MmapChoice::Always));
+        assert!(!mapped(&empty, MmapChoice::Always));
+    }
+}
@@ -1,99 +1,65 @@
-use std::error::Error;
-use std::{env, fs};
-
-pub struct Config {
-    pub query: String,
-    pub file_path: String,
-    pub ignore_case: bool,
-}
-
-impl Config {
-    pub fn build(args: &[String]) -> Result<Config, &'static str> {
-        if args.len() < 3 {
-            return Err("not enough arguments");
+use std::io::{self, Write};
+use std::path::Path;
+
+mod color;
+mod config;
+mod config_file;
+mod decompress;
+mod error;
+mod fuzzy;
+mod index;
+mod input;
+mod printer;
+mod replace;
+mod search;
+mod searcher;
+pub mod walk;
+
+pub use color::Colors;
+pub use config::{ArgsError, ColorChoice, Config, MmapChoice, OutputMode};
+pub use config_file::ConfigFile;
+pub use error::Error;
+pub use search::{search, search_case_insensitive, search_regex, Entry, Match, Matcher};
+pub use searcher::{Report, Searcher};
+
+/// Runs the search `config` describes and prints the results to stdout;
+/// `ColorChoice::Auto` means colour when stdout is a terminal.
+///
+/// For `minigrep index`, builds or refreshes the index of each path
+/// instead and reports what changed.
+pub fn run(mut config: Config) -> Result<Report, Error> {
+    config.color = color::for_stdout(config.color);
+    if config.build_index {
+        let mut out = io::stdout().lock();
+        let mut report = Report {
+            matched: true,
+            ..Report::default()
+        };
+        for path in &config.file_paths {
+            let summary = index::update(Path::new(path), &config)?;
+            report.errors.extend(summary.errors);
+            writeln!(
+                out,
+                "{path}: {} files indexed, {} updated, {} removed",
+                summary.files, summary.updated, summary.removed
+            )
+            .map_err(Error::Output)?;
         }
-
-        let query = args[1].clone();
-        let file_path = args[2].clone();
-
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
-
-        Ok(Config {
-            query,
-            file_path,
-            ignore_case,
-        })
+        return Ok(report);
     }
-}
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
-
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
-    } else {
-        search(&config.query, &contents)
-    };
-
-    for line in results {
-        println!("{line}")
-    }
-
-    Ok(())
+    Searcher::new(config)?.print(io::stdout().lock())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::test_config;
 
     #[test]
-    fn one_result() {
-        let query = "duct";
-        let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.
-Duct tape.";
+    fn invalid_regex_is_an_error() {
+        let config = test_config(&["-E", "fo(o", "poem.txt"]);
 
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+        assert!(run(config).is_err());
     }
-
-    #[test]
-    fn case_insensitive() {
-        let query = "rUsT";
-        let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.
-Trust me.";
-
-        assert_eq!(
-            vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
-        );
-    }
-}
-
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let mut results = Vec::new();
-    for line in contents.lines() {
-        // do something with line
-        if line.contains(query) {
-            results.push(line)
-        }
-    }
-    results
-}
-
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    let mut results = Vec::new();
-
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
-        }
-    }
-
-    results
 }
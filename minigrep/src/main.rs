@@ -1,23 +1,60 @@
 use std::env;
+use std::io::{self, Write};
 use std::process;
 
-use minigrep::Config;
+use minigrep::{ArgsError, Config, ConfigFile, Error};
+
+// like grep: a line was selected, none was, or something went wrong
+const EXIT_MATCH: i32 = 0;
+const EXIT_NO_MATCH: i32 = 1;
+const EXIT_ERROR: i32 = 2;
 
 fn main() {
     // --snip--
     let args: Vec<String> = env::args().collect();
 
-    let config = Config::build(&args).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
-    });
+    let mut config = ConfigFile::load(&args)
+        .and_then(|file| Config::build_with(&args, &file))
+        .unwrap_or_else(|err| {
+            if let ArgsError::Help | ArgsError::Version = err {
+                // `minigrep --help | head` closing the pipe early is fine
+                match writeln!(io::stdout(), "{err}") {
+                    Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                        eprintln!("Application error: {e}");
+                        process::exit(EXIT_ERROR);
+                    }
+                    _ => process::exit(EXIT_MATCH),
+                }
+            }
+            eprintln!("Problem parsing arguments: {err}");
+            process::exit(EXIT_ERROR);
+        });
 
-    println!("Searching for {}", config.query);
-    println!("In file {}", config.file_path);
+    // the environment is only a fallback for when no case option was given
+    if !config.case_chosen && env::var("IGNORE_CASE").is_ok() {
+        config.ignore_case = true;
+    }
 
-    if let Err(e) = minigrep::run(config) {
-        // --snip--
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    match minigrep::run(config) {
+        Ok(report) => {
+            for e in report.warnings.iter().chain(&report.errors) {
+                eprintln!("minigrep: {e}");
+            }
+            // an error trumps a match, as with grep
+            process::exit(if !report.errors.is_empty() {
+                EXIT_ERROR
+            } else if report.matched {
+                EXIT_MATCH
+            } else {
+                EXIT_NO_MATCH
+            });
+        }
+        // whoever was reading our output went away, e.g. `| head`
+        Err(Error::Output(e)) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(EXIT_MATCH),
+        Err(e) => {
+            // --snip--
+            eprintln!("Application error: {e}");
+            process::exit(EXIT_ERROR);
+        }
     }
 }
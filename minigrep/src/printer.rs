@@ -0,0 +1,466 @@
+use std::io::{self, BufRead, Write};
+use std::ops::{AddAssign, Range};
+
+use serde_json::{json, Value};
+
+use crate::color::Palette;
+use crate::search::{Entry, Match, Matcher};
+use crate::{Config, OutputMode};
+
+/// Running totals over the inputs searched so far, for the `--json` summary
+/// and the exit status. With `-l`/`-L` reading stops at a file's first
+/// match, so only that one is counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stats {
+    pub(crate) files_searched: usize,
+    pub(crate) files_with_matches: usize,
+    pub(crate) matched_lines: usize,
+    pub(crate) matches: usize,
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.files_searched += other.files_searched;
+        self.files_with_matches += other.files_with_matches;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+}
+
+/// Writes the results of searching one input after another to `out`, in
+/// whichever form `config` asks for.
+pub(crate) struct Printer<'a, W: Write> {
+    config: &'a Config,
+    palette: Palette,
+    out: W,
+    stats: Stats,
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    pub(crate) fn new(config: &'a Config, out: W) -> Printer<'a, W> {
+        Printer {
+            config,
+            palette: Palette::new(config.color, &config.colors),
+            out,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Searches `reader` and prints what was found, prefixing lines with
+    /// `name` when `show_name` is set.
+    pub(crate) fn search<R: BufRead>(
+        &mut self,
+        matcher: &Matcher,
+        reader: R,
+        name: &str,
+        show_name: bool,
+    ) -> io::Result<()> {
+        let config = self.config;
+        if config.output == OutputMode::Json {
+            return self.search_json(matcher, reader, name);
+        }
+        let name = self.palette.paint(&config.colors.path, name);
+
+        match config.output {
+            OutputMode::Lines | OutputMode::Json => {}
+            OutputMode::Count => {
+                let mut count = 0;
+                matcher.search_reader(reader, config.invert_match, 0, 0, |_| {
+                    count += 1;
+                    Ok(true)
+                })?;
+                self.tally(count, 0);
+
+                if show_name {
+                    let separator = self.palette.paint(&self.config.colors.separator, ":");
+                    writeln!(self.out, "{name}{separator}{count}")?;
+                } else {
+                    writeln!(self.out, "{count}")?;
+                }
+                return Ok(());
+            }
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => {
+                let mut found = false;
+                // one match settles it, no need to read the rest
+                matcher.search_reader(reader, config.invert_match, 0, 0, |_| {
+                    found = true;
+                    Ok(false)
+                })?;
+                self.tally(usize::from(found), 0);
+
+                if found == (config.output == OutputMode::FilesWithMatches) {
+                    writeln!(self.out, "{name}")?;
+                }
+                return Ok(());
+            }
+        }
+
+        if config.fuzzy.is_some() {
+            return self.search_ranked(matcher, reader, &name, show_name);
+        }
+
+        let (mut matched_lines, mut matches) = (0, 0);
+        matcher.search_reader(
+            reader,
+            config.invert_match,
+            config.before_context,
+            config.after_context,
+            |entry| {
+                // like grep, matches use ':' after each prefix and context lines '-'
+                match &entry {
+                    Entry::Match(result) => {
+                        matched_lines += 1;
+                        matches += result.spans.len();
+                        self.write_line(matcher, &name, show_name, result, ":")?;
+                    }
+                    Entry::Context(result) => {
+                        self.write_line(matcher, &name, show_name, result, "-")?;
+                    }
+                    Entry::Break => {
+                        let separator = self.palette.paint(&self.config.colors.separator, "--");
+                        writeln!(self.out, "{separator}")?;
+                    }
+                }
+                Ok(true)
+            },
+        )?;
+        self.tally(matched_lines, matches);
+
+        Ok(())
+    }
+
+    /// With `--fuzzy`, reads every match first so the closest ones can be
+    /// printed first; lines equally close stay in input order. Context
+    /// doesn't mean much once lines are out of order, so there isn't any.
+    fn search_ranked<R: BufRead>(
+        &mut self,
+        matcher: &Matcher,
+        reader: R,
+        name: &str,
+        show_name: bool,
+    ) -> io::Result<()> {
+        let ranked = rank(matcher, reader, self.config.invert_match)?;
+
+        let mut matches = 0;
+        for found in &ranked {
+            matches += found.spans.len();
+            self.write_line(matcher, name, show_name, &found.as_match(), ":")?;
+        }
+        self.tally(ranked.len(), matches);
+
+        Ok(())
+    }
+
+    /// Prints one line of `Lines` output, after the prefixes the config asks
+    /// for, each followed by `separator`. A multiline match is printed a
+    /// line at a time, each with its own prefixes.
+    fn write_line(
+        &mut self,
+        matcher: &Matcher,
+        name: &str,
+        show_name: bool,
+        result: &Match,
+        separator: &str,
+    ) -> io::Result<()> {
+        let config = self.config;
+        let palette = &self.palette;
+        let out = &mut self.out;
+        let separator = palette.paint(&config.colors.separator, separator);
+
+        let (text, spans) = match &config.replace {
+            Some(replacement) if !result.spans.is_empty() => {
+                matcher.replace(result.line, &result.spans, replacement)
+            }
+            _ => (result.line.to_string(), result.spans.clone()),
+        };
+
+        let mut start = 0;
+        for (i, piece) in text.split('\n').enumerate() {
+            let line = piece.strip_suffix('\r').unwrap_or(piece);
+            // the parts of the spans that fall on this line
+            let clip = |offset: usize| offset.clamp(start, start + line.len()) - start;
+            let spans: Vec<Range<usize>> = spans
+                .iter()
+                .map(|span| clip(span.start)..clip(span.end))
+                .filter(|span| !span.is_empty())
+                .collect();
+
+            if show_name {
+                write!(out, "{name}{separator}")?;
+            }
+            if config.line_number {
+                let number =
+                    palette.paint(&config.colors.number, &(result.line_number + i).to_string());
+                write!(out, "{number}{separator}")?;
+            }
+            if config.byte_offset {
+                let offset = palette.paint(
+                    &config.colors.number,
+                    &(result.byte_offset + start).to_string(),
+                );
+                write!(out, "{offset}{separator}")?;
+            }
+            writeln!(out, "{}", palette.highlight(line, &spans))?;
+
+            start += piece.len() + 1;
+        }
+
+        Ok(())
+    }
+
+    /// Adds one searched input with the given number of selected lines and
+    /// matches to the totals.
+    fn tally(&mut self, matched_lines: usize, matches: usize) {
+        self.stats += Stats {
+            files_searched: 1,
+            files_with_matches: usize::from(matched_lines > 0),
+            matched_lines,
+            matches,
+        };
+    }
+
+    /// Prints a `begin` record, one `match` or `context` record per line and
+    /// an `end` record with the totals for `name`. With `--fuzzy` the
+    /// matches come closest first, each with its `distance`.
+    fn search_json<R: BufRead>(
+        &mut self,
+        matcher: &Matcher,
+        reader: R,
+        name: &str,
+    ) -> io::Result<()> {
+        let config = self.config;
+        let out = &mut self.out;
+        let (mut matched_lines, mut matches) = (0, 0);
+
+        write_record(out, "begin", json!({ "path": name }))?;
+        if config.fuzzy.is_some() {
+            for found in rank(matcher, reader, config.invert_match)? {
+                matched_lines += 1;
+                matches += found.spans.len();
+                let mut record = line_record(name, &found.as_match());
+                record["distance"] = json!(found.distance);
+                write_record(out, "match", record)?;
+            }
+        } else {
+            matcher.search_reader(
+                reader,
+                config.invert_match,
+                config.before_context,
+                config.after_context,
+                |entry| {
+                    match entry {
+                        Entry::Match(result) => {
+                            matched_lines += 1;
+                            matches += result.spans.len();
+                            write_record(out, "match", line_record(name, &result))?;
+                        }
+                        Entry::Context(result) => {
+                            write_record(out, "context", line_record(name, &result))?;
+                        }
+                        // readers can tell groups apart by their line numbers
+                        Entry::Break => {}
+                    }
+                    Ok(true)
+                },
+            )?;
+        }
+        write_record(
+            out,
+            "end",
+            json!({
+                "path": name,
+                "matched_lines": matched_lines,
+                "matches": matches,
+            }),
+        )?;
+        self.tally(matched_lines, matches);
+
+        Ok(())
+    }
+
+    /// What has been found so far.
+    pub(crate) fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+/// A fuzzy match, kept until every line has been read and the matches can
+/// be put in order.
+struct Ranked {
+    distance: usize,
+    line_number: usize,
+    byte_offset: usize,
+    line: String,
+    spans: Vec<Range<usize>>,
+}
+
+impl Ranked {
+    fn as_match(&self) -> Match<'_> {
+        Match {
+            line_number: self.line_number,
+            byte_offset: self.byte_offset,
+            line: &self.line,
+            spans: self.spans.clone(),
+            distance: self.distance,
+        }
+    }
+}
+
+/// Every match in `reader`, closest first; lines equally close stay in
+/// input order.
+fn rank<R: BufRead>(matcher: &Matcher, reader: R, invert: bool) -> io::Result<Vec<Ranked>> {
+    let mut ranked = Vec::new();
+    matcher.search_reader(reader, invert, 0, 0, |entry| {
+        if let Entry::Match(result) = entry {
+            ranked.push(Ranked {
+                distance: result.distance,
+                line_number: result.line_number,
+                byte_offset: result.byte_offset,
+                line: result.line.to_string(),
+                spans: result.spans,
+            });
+        }
+        Ok(true)
+    })?;
+    ranked.sort_by_key(|found| found.distance);
+
+    Ok(ranked)
+}
+
+/// Prints the `summary` record that ends `--json` output.
+pub(crate) fn write_summary<W: Write>(out: &mut W, stats: Stats) -> io::Result<()> {
+    write_record(
+        out,
+        "summary",
+        json!({
+            "files_searched": stats.files_searched,
+            "files_with_matches": stats.files_with_matches,
+            "matched_lines": stats.matched_lines,
+            "matches": stats.matches,
+        }),
+    )
+}
+
+fn line_record(name: &str, result: &Match) -> Value {
+    let submatches: Vec<Value> = result
+        .spans
+        .iter()
+        .map(|span| {
+            json!({
+                "text": &result.line[span.clone()],
+                "start": span.start,
+                "end": span.end,
+            })
+        })
+        .collect();
+
+    json!({
+        "path": name,
+        "line_number": result.line_number,
+        // only differs from line_number for a multiline match
+        "end_line_number": result.line_number + result.line.matches('\n').count(),
+        "byte_offset": result.byte_offset,
+        "line": result.line,
+        "submatches": submatches,
+    })
+}
+
+fn write_record<W: Write>(out: &mut W, kind: &str, data: Value) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, &json!({ "type": kind, "data": data }))?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    #[test]
+    fn fuzzy_json_is_ranked() {
+        let config = test_config(&["--json", "--fuzzy=1", "frog"]);
+        let matcher = Matcher::new(&config).unwrap();
+        let mut out = Vec::new();
+
+        Printer::new(&config, &mut out)
+            .search(
+                &matcher,
+                "a frig
+a frog
+"
+                .as_bytes(),
+                "-",
+                false,
+            )
+            .unwrap();
+        let matches: Vec<(u64, u64)> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .filter(|record| record["type"] == "match")
+            .map(|record| {
+                let data = &record["data"];
+                (
+                    data["line_number"].as_u64().unwrap(),
+                    data["distance"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(vec![(2, 0), (1, 1)], matches);
+    }
+
+    #[test]
+    fn json_records() {
+        let config = test_config(&["--json", "frog"]);
+        let matcher = Matcher::new(&config).unwrap();
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&config, &mut out);
+
+        printer
+            .search(
+                &matcher,
+                "I'm nobody!\nlike a frog, frog\n".as_bytes(),
+                "poem.txt",
+                false,
+            )
+            .unwrap();
+        let stats = printer.stats();
+        let out = String::from_utf8(out).unwrap();
+        let records: Vec<Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(
+            vec![
+                json!({ "type": "begin", "data": { "path": "poem.txt" } }),
+                json!({ "type": "match", "data": {
+                    "path": "poem.txt",
+                    "line_number": 2,
+                    "end_line_number": 2,
+                    "byte_offset": 12,
+                    "line": "like a frog, frog",
+                    "submatches": [
+                        { "text": "frog", "start": 7, "end": 11 },
+                        { "text": "frog", "start": 13, "end": 17 },
+                    ],
+                } }),
+                json!({ "type": "end", "data": {
+                    "path": "poem.txt",
+                    "matched_lines": 1,
+                    "matches": 2,
+                } }),
+            ],
+            records
+        );
+        assert_eq!(
+            Stats {
+                files_searched: 1,
+                files_with_matches: 1,
+                matched_lines: 1,
+                matches: 2,
+            },
+            stats
+        );
+    }
+}
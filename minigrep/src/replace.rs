@@ -0,0 +1,190 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use tempfile::NamedTempFile;
+
+use crate::search::Matcher;
+use crate::walk;
+
+/// Rewrites `path` with every match replaced by `replacement`, returning how
+/// many lines changed.
+///
+/// The new contents are written to a temporary file next to the original
+/// and renamed over it, so readers see either the old file or the new one
+/// and never something half written. Unless `backup_suffix` is empty, the
+/// original is first copied to `path` + `backup_suffix`. Files without a
+/// match, and binary files, are left untouched.
+///
+/// A symbolic link is followed: the file it points to is the one rewritten,
+/// with the temporary file and backup next to it, and the link stays a link.
+pub(crate) fn rewrite_file(
+    matcher: &Matcher,
+    path: &Path,
+    replacement: &str,
+    backup_suffix: &str,
+) -> io::Result<usize> {
+    let path = &fs::canonicalize(path)?;
+    let mut reader = BufReader::new(File::open(path)?);
+    if walk::is_binary(reader.fill_buf()?) {
+        return Ok(0);
+    }
+
+    // a canonical path always has a parent, unless it's the root itself
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let mut temp = NamedTempFile::new_in(dir)?;
+    let changed = rewrite(
+        matcher,
+        reader,
+        BufWriter::new(temp.as_file_mut()),
+        replacement,
+    )?;
+
+    // nothing to do, the temporary file is removed when dropped
+    if changed == 0 {
+        return Ok(0);
+    }
+
+    let permissions = fs::metadata(path)?.permissions();
+    fs::set_permissions(temp.path(), permissions)?;
+
+    if !backup_suffix.is_empty() {
+        let mut backup = PathBuf::from(path).into_os_string();
+        backup.push(backup_suffix);
+        fs::copy(path, backup)?;
+    }
+
+    temp.persist(path).map_err(|e| e.error)?;
+
+    Ok(changed)
+}
+
+/// Copies `reader` to `out` line by line, replacing matches as it goes.
+/// Line endings are kept as they were, and lines that aren't valid UTF-8
+/// are copied through byte for byte.
+fn rewrite<R: BufRead, W: Write>(
+    matcher: &Matcher,
+    mut reader: R,
+    mut out: W,
+    replacement: &str,
+) -> io::Result<usize> {
+    let mut buf = Vec::new();
+    let mut changed = 0;
+
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+
+        let body_len = buf.len() - ending_len(&buf);
+        let (body, ending) = buf.split_at(body_len);
+
+        let Ok(line) = std::str::from_utf8(body) else {
+            out.write_all(&buf)?;
+            continue;
+        };
+
+        let spans = matcher.find_spans(line);
+        if spans.is_empty() {
+            out.write_all(&buf)?;
+            continue;
+        }
+
+        let (replaced, _) = matcher.replace(line, &spans, replacement);
+        out.write_all(replaced.as_bytes())?;
+        out.write_all(ending)?;
+        changed += 1;
+    }
+
+    out.flush()?;
+
+    Ok(changed)
+}
+
+fn ending_len(line: &[u8]) -> usize {
+    if line.ends_with(b"\r\n") {
+        2
+    } else if line.ends_with(b"\n") {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    fn matcher(args: &[&str]) -> Matcher {
+        Matcher::new(&test_config(args)).unwrap()
+    }
+
+    #[test]
+    fn keeps_line_endings_and_untouched_bytes() {
+        let input: &[u8] = b"old\r\nkeep \xff old\nold old";
+        let mut out = Vec::new();
+
+        let changed = rewrite(&matcher(&["old"]), input, &mut out, "new").unwrap();
+
+        assert_eq!(2, changed);
+        assert_eq!(b"new\r\nkeep \xff old\nnew new".to_vec(), out);
+    }
+
+    #[test]
+    fn rewrites_in_place_with_a_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lib.rs");
+        fs::write(&path, "fn run() {}\nrun();\n").unwrap();
+
+        let changed = rewrite_file(&matcher(&["-w", "run"]), &path, "start", ".orig").unwrap();
+
+        assert_eq!(2, changed);
+        assert_eq!(
+            "fn start() {}\nstart();\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        assert_eq!(
+            "fn run() {}\nrun();\n",
+            fs::read_to_string(dir.path().join("lib.rs.orig")).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_followed_and_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real.txt");
+        let link = dir.path().join("d/link.txt");
+        fs::write(&real, "old\n").unwrap();
+        fs::create_dir(dir.path().join("d")).unwrap();
+        std::os::unix::fs::symlink("../real.txt", &link).unwrap();
+
+        assert_eq!(
+            1,
+            rewrite_file(&matcher(&["old"]), &link, "new", ".bak").unwrap()
+        );
+
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!("new\n", fs::read_to_string(&real).unwrap());
+        assert_eq!(
+            "old\n",
+            fs::read_to_string(dir.path().join("real.txt.bak")).unwrap()
+        );
+        assert!(!dir.path().join("d/link.txt.bak").exists());
+    }
+
+    #[test]
+    fn files_without_matches_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "nothing here\n").unwrap();
+
+        assert_eq!(
+            0,
+            rewrite_file(&matcher(&["run"]), &path, "start", ".bak").unwrap()
+        );
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+}
@@ -0,0 +1,1194 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use caseless::Caseless;
+use memchr::memmem::Finder;
+use memchr::{memchr, memchr_iter, memrchr};
+use regex::{Regex, RegexBuilder};
+
+use crate::fuzzy::Fuzzy;
+use crate::{Config, Error};
+
+/// A line that matched the query, along with where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'a> {
+    /// 1-based line number.
+    pub line_number: usize,
+    /// Byte offset of the start of the line within the searched input.
+    pub byte_offset: usize,
+    pub line: &'a str,
+    /// Byte ranges within `line` covered by the query. Empty for the lines
+    /// reported by an inverted search.
+    pub spans: Vec<Range<usize>>,
+    /// With `--fuzzy`, how many edits away from the query the match is;
+    /// otherwise always 0.
+    pub distance: usize,
+}
+
+/// A line of search output once context lines are taken into account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry<'a> {
+    Match(Match<'a>),
+    /// A line shown only because it is near a match; its spans are empty.
+    Context(Match<'a>),
+    /// Sits between two groups of lines that aren't adjacent in the input.
+    Break,
+}
+
+/// The query, prepared once so each line only pays for the comparison.
+#[derive(Debug)]
+pub struct Matcher {
+    pattern: Pattern,
+    anchor: Anchor,
+    /// Search the whole input at once so matches can span lines.
+    multiline: bool,
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Literal(String),
+    // holds the already case folded query
+    CaseInsensitive(String),
+    /// Any number of literal queries, found in a single pass over the line.
+    /// With `folded` the queries were case folded and lines get the same.
+    Literals {
+        automaton: AhoCorasick,
+        folded: bool,
+    },
+    Regex(Regex),
+    /// Approximate matching; a line matches if any of these is close enough.
+    Fuzzy(Vec<Fuzzy>),
+}
+
+/// Where a match has to sit within the line to count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Anchor {
+    Anywhere,
+    /// Not preceded or followed by a word character, like `grep -w`.
+    Word,
+    /// Covering the whole line, like `grep -x`.
+    Line,
+}
+
+impl From<Pattern> for Matcher {
+    fn from(pattern: Pattern) -> Matcher {
+        Matcher {
+            pattern,
+            anchor: Anchor::Anywhere,
+            multiline: false,
+        }
+    }
+}
+
+impl Matcher {
+    pub fn new(config: &Config) -> Result<Matcher, Error> {
+        let patterns = &config.patterns;
+
+        // with smart case an uppercase letter in the query is taken to mean
+        // the user cares about case
+        let ignore_case = config.ignore_case
+            || (config.smart_case && !patterns.iter().any(|p| has_uppercase(p, config.regex)));
+
+        let anchor = if config.line_regexp {
+            Anchor::Line
+        } else if config.word_regexp {
+            Anchor::Word
+        } else {
+            Anchor::Anywhere
+        };
+
+        let pattern = if let Some(max_edits) = config.fuzzy {
+            let queries = patterns
+                .iter()
+                .map(|p| Fuzzy::new(p, max_edits, config.transpositions, ignore_case, anchor))
+                .collect();
+            Pattern::Fuzzy(queries)
+        } else if (config.regex || config.multiline) && !patterns.is_empty() {
+            // only the regex engine can look past the end of a line
+            let alternation = patterns
+                .iter()
+                .map(|p| {
+                    if config.regex {
+                        format!("(?:{p})")
+                    } else {
+                        regex::escape(p)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("|");
+            // let the regex engine handle the anchoring, so it can keep
+            // looking for a match that fits rather than giving up on the
+            // first one that doesn't
+            let query = match anchor {
+                Anchor::Anywhere => alternation,
+                Anchor::Word => format!(r"\b{{start-half}}(?:{alternation})\b{{end-half}}"),
+                Anchor::Line => format!("^(?:{alternation})$"),
+            };
+            // the regex engine only does simple case folding, one character
+            // to one, so unlike the literal patterns ß won't match SS here;
+            // --help says as much
+            let re = RegexBuilder::new(&query)
+                .case_insensitive(ignore_case)
+                // ^ and $ still mean the start and end of a line
+                .multi_line(config.multiline)
+                .crlf(config.multiline)
+                .build()?;
+            Pattern::Regex(re)
+        } else if patterns.len() == 1 && ignore_case {
+            Pattern::CaseInsensitive(fold_case(&patterns[0]))
+        } else if patterns.len() == 1 {
+            Pattern::Literal(patterns[0].clone())
+        } else {
+            // with no patterns at all (an empty -f file) this never matches,
+            // same as grep
+            let queries: Vec<String> = if ignore_case {
+                patterns.iter().map(|p| fold_case(p)).collect()
+            } else {
+                patterns.clone()
+            };
+            // a whole word may hide behind a longer match that isn't one,
+            // as "hello" does behind "hello w" in "hello world", so with -w
+            // every match is found and the ones that fit picked afterwards
+            let kind = if anchor == Anchor::Word {
+                MatchKind::Standard
+            } else {
+                MatchKind::LeftmostLongest
+            };
+            let automaton = AhoCorasick::builder().match_kind(kind).build(&queries)?;
+            Pattern::Literals {
+                automaton,
+                folded: ignore_case,
+            }
+        };
+
+        Ok(Matcher {
+            pattern,
+            anchor,
+            multiline: config.multiline,
+        })
+    }
+
+    /// Byte ranges of every occurrence of the query in `line`.
+    pub fn find_spans(&self, line: &str) -> Vec<Range<usize>> {
+        let find_str = |haystack: &str, query: &str| -> Vec<Range<usize>> {
+            haystack
+                .match_indices(query)
+                .map(|(start, m)| start..start + m.len())
+                .collect()
+        };
+        let find_any = |haystack: &str, automaton: &AhoCorasick| -> Vec<Range<usize>> {
+            if self.anchor == Anchor::Word {
+                automaton
+                    .find_overlapping_iter(haystack)
+                    .map(|m| m.range())
+                    .collect()
+            } else {
+                automaton.find_iter(haystack).map(|m| m.range()).collect()
+            }
+        };
+
+        let (found, origins) = match &self.pattern {
+            // anchoring was built into the regex itself
+            Pattern::Regex(re) => return re.find_iter(line).map(|m| m.range()).collect(),
+            Pattern::Fuzzy(_) => {
+                return self
+                    .closest(line)
+                    .into_iter()
+                    .map(|(_, span)| span)
+                    .collect();
+            }
+            Pattern::Literal(query) => (find_str(line, query), None),
+            Pattern::CaseInsensitive(query) => {
+                let (folded, origins) = fold_case_with_origins(line);
+                (find_str(&folded, query), Some(origins))
+            }
+            Pattern::Literals {
+                automaton,
+                folded: false,
+            } => (find_any(line, automaton), None),
+            Pattern::Literals {
+                automaton,
+                folded: true,
+            } => {
+                let (folded, origins) = fold_case_with_origins(line);
+                (find_any(&folded, automaton), Some(origins))
+            }
+        };
+
+        let spans: Vec<Range<usize>> = found
+            .into_iter()
+            .map(|span| match &origins {
+                Some(origins) => {
+                    origins[span.start]..original_end(line, origins, span.start, span.end)
+                }
+                None => span,
+            })
+            .filter(|span| self.fits(line, span))
+            .collect();
+
+        if matches!(self.pattern, Pattern::Literals { .. }) && self.anchor == Anchor::Word {
+            leftmost_longest(spans)
+        } else {
+            spans
+        }
+    }
+
+    /// How many edits away from the query the closest match in `line` is:
+    /// with `--fuzzy` anything up to its limit, otherwise always 0. `None`
+    /// when nothing in the line matches.
+    pub fn distance(&self, line: &str) -> Option<usize> {
+        match &self.pattern {
+            Pattern::Fuzzy(_) => self.closest(line).map(|(distance, _)| distance),
+            _ => (!self.find_spans(line).is_empty()).then_some(0),
+        }
+    }
+
+    /// `find_spans` and `distance` in one go, so a fuzzy match is only
+    /// worked out once.
+    fn find(&self, line: &str) -> (Vec<Range<usize>>, usize) {
+        match &self.pattern {
+            Pattern::Fuzzy(_) => match self.closest(line) {
+                Some((distance, span)) => (vec![span], distance),
+                None => (Vec::new(), 0),
+            },
+            _ => (self.find_spans(line), 0),
+        }
+    }
+
+    /// The best approximate match for any of the fuzzy queries.
+    fn closest(&self, line: &str) -> Option<(usize, Range<usize>)> {
+        let Pattern::Fuzzy(queries) = &self.pattern else {
+            return None;
+        };
+
+        // each query already only looks where the anchor allows
+        queries
+            .iter()
+            .filter_map(|query| query.find(line))
+            .min_by_key(|(distance, _)| *distance)
+    }
+
+    /// Whether a match at `span` sits where the anchor wants it.
+    fn fits(&self, line: &str, span: &Range<usize>) -> bool {
+        match self.anchor {
+            Anchor::Anywhere => true,
+            Anchor::Word => is_word(line, span),
+            // the leftmost, longest match is the whole line if anything is
+            Anchor::Line => *span == (0..line.len()),
+        }
+    }
+
+    /// Replaces the matches in `line` (as found by `find_spans`) with
+    /// `replacement`, returning the new line and where the replacements
+    /// ended up in it. In regex mode `$1` or `${name}` in the replacement
+    /// refer to capture groups; otherwise it is used as is.
+    pub fn replace(
+        &self,
+        line: &str,
+        spans: &[Range<usize>],
+        replacement: &str,
+    ) -> (String, Vec<Range<usize>>) {
+        let mut out = String::with_capacity(line.len());
+        let mut new_spans = Vec::with_capacity(spans.len());
+        let mut last = 0;
+
+        let mut push = |span: Range<usize>, with: &dyn Fn(&mut String)| {
+            out.push_str(&line[last..span.start]);
+            let start = out.len();
+            with(&mut out);
+            new_spans.push(start..out.len());
+            last = span.end;
+        };
+
+        match &self.pattern {
+            Pattern::Regex(re) => {
+                for caps in re.captures_iter(line) {
+                    let span = caps.get(0).map_or(0..0, |m| m.range());
+                    push(span, &|out: &mut String| caps.expand(replacement, out));
+                }
+            }
+            _ => {
+                for span in spans {
+                    push(span.clone(), &|out: &mut String| out.push_str(replacement));
+                }
+            }
+        }
+        out.push_str(&line[last..]);
+
+        (out, new_spans)
+    }
+
+    /// Every line that matches, or with `invert` every line that doesn't.
+    pub fn search<'a>(&self, contents: &'a str, invert: bool) -> Vec<Match<'a>> {
+        self.search_with_context(contents, invert, 0, 0)
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Match(m) => Some(m),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Like `search`, but also includes up to `before`/`after` lines around
+    /// each match. Context that overlaps is only reported once, and a
+    /// `Break` separates groups that aren't next to each other.
+    pub fn search_with_context<'a>(
+        &self,
+        contents: &'a str,
+        invert: bool,
+        before: usize,
+        after: usize,
+    ) -> Vec<Entry<'a>> {
+        // `contents` is already valid UTF-8, so every line the reader hands
+        // back is byte-for-byte a slice of it and can borrow from it instead
+        let borrow = |m: Match| Match {
+            line: &contents[m.byte_offset..m.byte_offset + m.line.len()],
+            ..m
+        };
+
+        let mut entries = Vec::new();
+        self.search_reader(contents.as_bytes(), invert, before, after, |entry| {
+            entries.push(match entry {
+                Entry::Match(m) => Entry::Match(borrow(m)),
+                Entry::Context(m) => Entry::Context(borrow(m)),
+                Entry::Break => Entry::Break,
+            });
+            Ok(true)
+        })
+        .expect("reading from a byte slice can't fail");
+
+        entries
+    }
+
+    /// Streams `reader` line by line, handing each entry to `emit` as soon as
+    /// it is known, so memory use depends on line length and `before` rather
+    /// than on the size of the input. Invalid UTF-8 is replaced with U+FFFD.
+    ///
+    /// `emit` returns whether to keep going, which lets callers that only
+    /// care about the first match stop reading early.
+    ///
+    /// In multiline mode the whole input is read first instead, see
+    /// `search_multiline`. Plain literal searches without context take a
+    /// faster route, see `search_literal`.
+    pub fn search_reader<R: BufRead>(
+        &self,
+        mut reader: R,
+        invert: bool,
+        before: usize,
+        after: usize,
+        mut emit: impl FnMut(Entry<'_>) -> io::Result<bool>,
+    ) -> io::Result<()> {
+        if let (true, Pattern::Regex(re)) = (self.multiline, &self.pattern) {
+            return search_multiline(re, reader, invert, before, after, emit);
+        }
+        if !invert && before == 0 && after == 0 {
+            match &self.pattern {
+                Pattern::Literal(query) if !query.is_empty() => {
+                    let finder = Finder::new(query);
+                    return self.search_literal(reader, |haystack| finder.find(haystack), emit);
+                }
+                // an empty pattern matches every line, which the line loop
+                // handles without special cases
+                Pattern::Literals {
+                    automaton,
+                    folded: false,
+                } if automaton.min_pattern_len() > 0 => {
+                    let find = |haystack: &[u8]| automaton.find(haystack).map(|m| m.start());
+                    return self.search_literal(reader, find, emit);
+                }
+                _ => {}
+            }
+        }
+
+        let mut buf = Vec::new();
+        let mut line_number = 0;
+        let mut byte_offset = 0;
+        // (line number, byte offset, text) of lines we may still need as
+        // before-context, all of them after `last_shown`
+        let mut pending: VecDeque<(usize, usize, String)> = VecDeque::with_capacity(before);
+        let mut last_shown: Option<usize> = None;
+        let mut after_left = 0;
+
+        loop {
+            buf.clear();
+            let read = reader.read_until(b'\n', &mut buf)?;
+            if read == 0 {
+                return Ok(());
+            }
+
+            line_number += 1;
+            let offset = byte_offset;
+            byte_offset += read;
+
+            let raw = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            let raw = raw.strip_suffix(b"\r").unwrap_or(raw);
+            let line = String::from_utf8_lossy(raw);
+            let (spans, distance) = self.find(&line);
+
+            if spans.is_empty() == invert {
+                let first = pending
+                    .front()
+                    .map_or(line_number, |(number, _, _)| *number);
+                // groups only exist when there's context to group
+                let context = before > 0 || after > 0;
+                if context
+                    && last_shown.is_some_and(|last| first > last + 1)
+                    && !emit(Entry::Break)?
+                {
+                    return Ok(());
+                }
+
+                for (number, offset, text) in pending.drain(..) {
+                    let context = Match {
+                        line_number: number,
+                        byte_offset: offset,
+                        line: &text,
+                        spans: Vec::new(),
+                        distance: 0,
+                    };
+                    if !emit(Entry::Context(context))? {
+                        return Ok(());
+                    }
+                }
+
+                let record = Match {
+                    line_number,
+                    byte_offset: offset,
+                    line: &line,
+                    spans,
+                    distance,
+                };
+                if !emit(Entry::Match(record))? {
+                    return Ok(());
+                }
+                last_shown = Some(line_number);
+                after_left = after;
+            } else if after_left > 0 {
+                let context = Match {
+                    line_number,
+                    byte_offset: offset,
+                    line: &line,
+                    spans: Vec::new(),
+                    distance: 0,
+                };
+                if !emit(Entry::Context(context))? {
+                    return Ok(());
+                }
+                last_shown = Some(line_number);
+                after_left -= 1;
+            } else if before > 0 {
+                if pending.len() == before {
+                    pending.pop_front();
+                }
+                pending.push_back((line_number, offset, line.into_owned()));
+            }
+        }
+    }
+
+    /// Searches `reader` a buffer-full at a time rather than a line at a
+    /// time: `find` locates the next possible match anywhere in the buffer
+    /// (with memchr's SIMD substring search, or Aho-Corasick for several
+    /// literals), and only the line around it is decoded and checked with
+    /// `find_spans`. Lines without a hit are never looked at individually;
+    /// line numbers come from counting newlines, which is fast too.
+    ///
+    /// Only the lines in the reader's own buffer are searched in place; a
+    /// line cut off at the end of it is copied until the rest arrives.
+    fn search_literal<R: BufRead>(
+        &self,
+        mut reader: R,
+        find: impl Fn(&[u8]) -> Option<usize>,
+        mut emit: impl FnMut(Entry<'_>) -> io::Result<bool>,
+    ) -> io::Result<()> {
+        let mut partial = Vec::new();
+        // line number and byte offset of the start of the next region
+        let mut position = (0, 0);
+
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                // the last line, without a line break
+                if !partial.is_empty() {
+                    self.scan(&partial, &find, &mut position, &mut emit)?;
+                }
+                return Ok(());
+            }
+
+            let used = if partial.is_empty() {
+                let end = memrchr(b'\n', chunk).map_or(0, |i| i + 1);
+                if !self.scan(&chunk[..end], &find, &mut position, &mut emit)? {
+                    return Ok(());
+                }
+                partial.extend_from_slice(&chunk[end..]);
+                chunk.len()
+            } else if let Some(i) = memchr(b'\n', chunk) {
+                partial.extend_from_slice(&chunk[..=i]);
+                if !self.scan(&partial, &find, &mut position, &mut emit)? {
+                    return Ok(());
+                }
+                partial.clear();
+                i + 1
+            } else {
+                partial.extend_from_slice(chunk);
+                chunk.len()
+            };
+            reader.consume(used);
+        }
+    }
+
+    /// Emits every line in `region`, which holds whole lines starting at
+    /// `position`, that has a hit. Returns whether `emit` wants more.
+    fn scan(
+        &self,
+        region: &[u8],
+        find: &impl Fn(&[u8]) -> Option<usize>,
+        position: &mut (usize, usize),
+        emit: &mut impl FnMut(Entry<'_>) -> io::Result<bool>,
+    ) -> io::Result<bool> {
+        let (line_number, byte_offset) = position;
+        // newlines before `counted` are already in `line_number`
+        let mut counted = 0;
+        let mut next = 0;
+
+        while let Some(hit) = find(&region[next..]) {
+            let hit = next + hit;
+            let start = memrchr(b'\n', &region[next..hit]).map_or(next, |i| next + i + 1);
+            let end = memchr(b'\n', &region[hit..]).map_or(region.len(), |i| hit + i);
+            *line_number += memchr_iter(b'\n', &region[counted..start]).count();
+            counted = start;
+
+            let raw = &region[start..end];
+            let line = String::from_utf8_lossy(raw.strip_suffix(b"\r").unwrap_or(raw));
+            let spans = self.find_spans(&line);
+            if !spans.is_empty() {
+                let record = Match {
+                    line_number: *line_number + 1,
+                    byte_offset: *byte_offset + start,
+                    line: &line,
+                    spans,
+                    distance: 0,
+                };
+                if !emit(Entry::Match(record))? {
+                    return Ok(false);
+                }
+            }
+
+            next = end + 1;
+            if next >= region.len() {
+                break;
+            }
+        }
+
+        *line_number += memchr_iter(b'\n', &region[counted..]).count();
+        *byte_offset += region.len();
+        Ok(true)
+    }
+}
+
+/// Picks, out of possibly overlapping `spans`, the leftmost and then the
+/// longest, then the same again after its end, and so on.
+fn leftmost_longest(mut spans: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    spans.sort_by_key(|span| (span.start, Reverse(span.end)));
+
+    let mut picked: Vec<Range<usize>> = Vec::with_capacity(spans.len());
+    for span in spans {
+        if picked.last().is_none_or(|last| span.start >= last.end) {
+            picked.push(span);
+        }
+    }
+    picked
+}
+
+/// Runs `re` over all of `reader` at once, so a match may span several
+/// lines. Each match is reported as a single `Match` whose `line` holds
+/// every line it touches, joined by their line breaks, and whose
+/// `line_number` is that of the first one. Matches that share a line are
+/// reported together. Context and `invert` work a line at a time, as in
+/// `Matcher::search_reader`.
+fn search_multiline<R: BufRead>(
+    re: &Regex,
+    mut reader: R,
+    invert: bool,
+    before: usize,
+    after: usize,
+    mut emit: impl FnMut(Entry<'_>) -> io::Result<bool>,
+) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let text = String::from_utf8_lossy(&bytes);
+
+    // where each line is in `text`, leaving out its line break
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for piece in text.split_inclusive('\n') {
+        let body = piece.strip_suffix('\n').unwrap_or(piece);
+        let body = body.strip_suffix('\r').unwrap_or(body);
+        lines.push(start..start + body.len());
+        start += piece.len();
+    }
+    let line_of = |offset: usize| lines.partition_point(|line| line.start <= offset) - 1;
+
+    // (first line, last line, match ranges in `text`) for each run of
+    // matches that share lines
+    let mut found: Vec<(usize, usize, Vec<Range<usize>>)> = Vec::new();
+    for m in re.find_iter(&text).filter(|_| !lines.is_empty()) {
+        let first = line_of(m.start());
+        let last = if m.is_empty() {
+            first
+        } else {
+            line_of(m.end() - 1)
+        };
+        match found.last_mut() {
+            Some((_, end, ranges)) if first <= *end => {
+                *end = last.max(*end);
+                ranges.push(m.range());
+            }
+            _ => found.push((first, last, vec![m.range()])),
+        }
+    }
+
+    let selected = if invert {
+        let mut unmatched = Vec::new();
+        let mut next = 0;
+        for &(first, last, _) in found
+            .iter()
+            .chain([&(lines.len(), lines.len(), Vec::new())])
+        {
+            unmatched.extend((next..first).map(|line| (line, line, Vec::new())));
+            next = last + 1;
+        }
+        unmatched
+    } else {
+        found
+    };
+
+    let record = |first: usize, last: usize, ranges: &[Range<usize>]| {
+        let start = lines[first].start;
+        let line = &text[start..lines[last].end];
+        Match {
+            line_number: first + 1,
+            byte_offset: start,
+            line,
+            spans: ranges
+                .iter()
+                .map(|range| {
+                    (range.start - start).min(line.len())..(range.end - start).min(line.len())
+                })
+                .collect(),
+            distance: 0,
+        }
+    };
+    let context = before > 0 || after > 0;
+    // lines before `shown` have been emitted; after-context runs to `after_end`
+    let (mut shown, mut after_end) = (0, 0);
+
+    for (first, last, ranges) in selected {
+        let context_end = after_end.min(first);
+        for line in shown..context_end {
+            if !emit(Entry::Context(record(line, line, &[])))? {
+                return Ok(());
+            }
+        }
+        shown = shown.max(context_end);
+
+        let from = first.saturating_sub(before).max(shown);
+        if context && shown > 0 && from > shown && !emit(Entry::Break)? {
+            return Ok(());
+        }
+        for line in from..first {
+            if !emit(Entry::Context(record(line, line, &[])))? {
+                return Ok(());
+            }
+        }
+        if !emit(Entry::Match(record(first, last, &ranges)))? {
+            return Ok(());
+        }
+        shown = last + 1;
+        after_end = last + 1 + after;
+    }
+
+    for line in shown..after_end.min(lines.len()) {
+        if !emit(Entry::Context(record(line, line, &[])))? {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `span` of `line` stands on its own as a word, i.e. isn't
+/// directly preceded or followed by a word character.
+fn is_word(line: &str, span: &Range<usize>) -> bool {
+    let before = line[..span.start].chars().next_back();
+    let after = line[span.end..].chars().next();
+
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+pub(crate) fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Applies full Unicode case folding, so "straße" and "STRASSE" both come
+/// out as "strasse".
+pub(crate) fn fold_case(text: &str) -> String {
+    text.chars().default_case_fold().collect()
+}
+
+/// Case folds `line`, also returning for every byte of the result the offset
+/// of the character in `line` it came from. The extra trailing entry maps the
+/// end of the folded string, so ranges can be translated end-exclusive.
+///
+/// Folding can change a character's length, so the offsets of a match in
+/// the folded string can't be used on the original line directly.
+pub(crate) fn fold_case_with_origins(line: &str) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(line.len());
+    let mut origins = Vec::with_capacity(line.len() + 1);
+
+    for (offset, c) in line.char_indices() {
+        for f in std::iter::once(c).default_case_fold() {
+            folded.push(f);
+            origins.extend(std::iter::repeat_n(offset, f.len_utf8()));
+        }
+    }
+    origins.push(line.len());
+
+    (folded, origins)
+}
+
+/// Maps the end of a match in the folded line back to `line`. A match can
+/// stop partway through what one character folded into ("stras" against
+/// "straße"), in which case the whole character is included.
+pub(crate) fn original_end(line: &str, origins: &[usize], start: usize, end: usize) -> usize {
+    let origin = origins[end];
+
+    if end > start && origins[end - 1] == origin {
+        origin + line[origin..].chars().next().map_or(0, char::len_utf8)
+    } else {
+        origin
+    }
+}
+
+/// Whether `query` contains an uppercase letter. In a regex, escapes such
+/// as `\W` or `\S` are syntax rather than text and don't count.
+fn has_uppercase(query: &str, regex: bool) -> bool {
+    let mut chars = query.chars();
+
+    while let Some(c) = chars.next() {
+        if regex && c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+
+    false
+}
+
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    Matcher::from(Pattern::Literal(query.to_string())).search(contents, false)
+}
+
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    Matcher::from(Pattern::CaseInsensitive(fold_case(query))).search(contents, false)
+}
+
+pub fn search_regex<'a>(re: &Regex, contents: &'a str) -> Vec<Match<'a>> {
+    Matcher::from(Pattern::Regex(re.clone())).search(contents, false)
+}
+
+#[cfg(test)]
+// spans are a list of ranges, so `vec![a..b]` is exactly what we mean
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    fn lines<'a>(results: &[Match<'a>]) -> Vec<&'a str> {
+        results.iter().map(|m| m.line).collect()
+    }
+
+    #[test]
+    fn one_result() {
+        let query = "duct";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+
+        assert_eq!(
+            vec![Match {
+                line_number: 2,
+                byte_offset: 6,
+                line: "safe, fast, productive.",
+                spans: vec![15..19],
+                distance: 0,
+            }],
+            search(query, contents)
+        );
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let query = "rUsT";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(
+            vec!["Rust:", "Trust me."],
+            lines(&search_case_insensitive(query, contents))
+        );
+    }
+
+    #[test]
+    fn regex_pattern() {
+        let re = Regex::new(r"fo+\d").unwrap();
+        let contents = "\
+fo1 started
+f2 skipped
+foooo9 finished
+foo done";
+
+        assert_eq!(
+            vec!["fo1 started", "foooo9 finished"],
+            lines(&search_regex(&re, contents))
+        );
+    }
+
+    #[test]
+    fn offsets_account_for_crlf() {
+        let contents = "one\r\ntwo\r\nthree two";
+
+        let results = search("two", contents);
+
+        assert_eq!(
+            vec![2, 3],
+            results.iter().map(|m| m.line_number).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![5, 10],
+            results.iter().map(|m| m.byte_offset).collect::<Vec<_>>()
+        );
+        assert_eq!(vec![6..9], results[1].spans);
+    }
+
+    #[test]
+    fn context_groups_are_merged_and_separated() {
+        let contents = "a\nb\nhit\nc\nhit\nd\ne\nf\ng\nhit\nh";
+        let matcher = Matcher::from(Pattern::Literal(String::from("hit")));
+
+        let shown: Vec<String> = matcher
+            .search_with_context(contents, false, 1, 1)
+            .iter()
+            .map(|entry| match entry {
+                Entry::Match(m) => format!("{}:{}", m.line_number, m.line),
+                Entry::Context(m) => format!("{}-{}", m.line_number, m.line),
+                Entry::Break => String::from("--"),
+            })
+            .collect();
+
+        assert_eq!(
+            vec!["2-b", "3:hit", "4-c", "5:hit", "6-d", "--", "9-g", "10:hit", "11-h"],
+            shown
+        );
+    }
+
+    #[test]
+    fn no_breaks_without_context() {
+        let matcher = Matcher::from(Pattern::Literal(String::from("hit")));
+
+        let entries = matcher.search_with_context("hit\nmiss\nhit", false, 0, 0);
+
+        assert!(!entries.contains(&Entry::Break));
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let matcher = Matcher::from(Pattern::Literal(String::from("frog")));
+        let input: &[u8] = b"caf\xe9 frog\nplain\n";
+        let mut lines = Vec::new();
+
+        matcher
+            .search_reader(input, false, 0, 0, |entry| {
+                if let Entry::Match(m) = entry {
+                    lines.push((m.line.to_string(), m.spans));
+                }
+                Ok(true)
+            })
+            .unwrap();
+
+        assert_eq!(vec![(String::from("caf\u{fffd} frog"), vec![7..11])], lines);
+    }
+
+    #[test]
+    fn emit_can_stop_the_search() {
+        let matcher = Matcher::from(Pattern::Literal(String::from("o")));
+        let mut seen = 0;
+
+        matcher
+            .search_reader("one\ntwo\nfour\n".as_bytes(), false, 0, 0, |_| {
+                seen += 1;
+                Ok(false)
+            })
+            .unwrap();
+
+        assert_eq!(1, seen);
+    }
+
+    #[test]
+    fn case_insensitive_spans_point_into_the_original_line() {
+        // 'İ' folds to two characters, shifting everything after it
+        let results = search_case_insensitive("rust", "İ RUST");
+
+        assert_eq!(vec![3..7], results[0].spans);
+    }
+
+    #[test]
+    fn full_case_folding() {
+        let results = search_case_insensitive("straße", "Hauptstraße\nHAUPTSTRASSE\nStrasbourg");
+
+        assert_eq!(vec!["Hauptstraße", "HAUPTSTRASSE"], lines(&results));
+        assert_eq!(vec![5..12], results[1].spans);
+    }
+
+    #[test]
+    fn partial_fold_takes_the_whole_character() {
+        let results = search_case_insensitive("STRAS", "straße");
+
+        assert_eq!(vec![0..6], results[0].spans);
+    }
+
+    fn matcher(args: &[&str]) -> Matcher {
+        Matcher::new(&test_config(args)).unwrap()
+    }
+
+    #[test]
+    fn whole_words_only() {
+        let contents = "run\nrunning\ntruncate\nfn run() {\nrerun run_fast";
+
+        for args in [&["-w", "run"][..], &["-wi", "RUN"], &["-wE", "ru?n"]] {
+            let results = matcher(args).search(contents, false);
+            assert_eq!(vec!["run", "fn run() {"], lines(&results), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn word_regex_keeps_looking_for_a_fit() {
+        let results = matcher(&["-wE", "run|running"]).search("running late", false);
+
+        assert_eq!(vec![0..7], results[0].spans);
+    }
+
+    #[test]
+    fn whole_lines_only() {
+        let contents = "run\nRun\nrun away";
+
+        assert_eq!(
+            vec!["run"],
+            lines(&matcher(&["-x", "run"]).search(contents, false))
+        );
+        assert_eq!(
+            vec!["run", "Run"],
+            lines(&matcher(&["-xi", "run"]).search(contents, false))
+        );
+        assert_eq!(
+            vec!["run", "run away"],
+            lines(&matcher(&["-xE", "run|run away"]).search(contents, false))
+        );
+    }
+
+    #[test]
+    fn any_of_several_literals() {
+        let contents = "let x = y.unwrap();\nok\nlet z = w.expect(\"z\");\nUNWRAP_OR";
+
+        let results =
+            matcher(&["-e", "unwrap", "-e", "expect", "-e", "unwrap_or"]).search(contents, false);
+        assert_eq!(vec![10..16], results[0].spans);
+        assert_eq!(vec![10..16], results[1].spans);
+        assert_eq!(2, results.len());
+
+        let results = matcher(&["-i", "-e", "unwrap", "-e", "unwrap_or"]).search(contents, false);
+        assert_eq!(vec![0..9], results[1].spans);
+    }
+
+    #[test]
+    fn several_literals_respect_anchors() {
+        let contents = "run\nrun away\nrunning\nto run";
+        let m = matcher(&["-x", "-e", "run", "-e", "run away"]);
+        assert_eq!(vec!["run", "run away"], lines(&m.search(contents, false)));
+
+        let m = matcher(&["-w", "-e", "run", "-e", "walk"]);
+        assert_eq!(
+            vec!["run", "run away", "to run"],
+            lines(&m.search(contents, false))
+        );
+    }
+
+    #[test]
+    fn several_words_keep_looking_for_a_fit() {
+        let m = matcher(&["-w", "-e", "hello", "-e", "hello w"]);
+        assert_eq!(vec![0..5], m.search("hello world", false)[0].spans);
+
+        let m = matcher(&["-w", "-i", "-e", "run", "-e", "running"]);
+        assert_eq!(vec![0..7, 8..11], m.search("RUNNING run", false)[0].spans);
+    }
+
+    #[test]
+    fn several_regexes() {
+        let results =
+            matcher(&["-E", "-e", r"fo+\d", "-e", "^bar"]).search("fooo1\nbar\nbaz", false);
+
+        assert_eq!(vec!["fooo1", "bar"], lines(&results));
+    }
+
+    #[test]
+    fn empty_pattern_file_matches_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty.txt");
+        std::fs::write(&empty, "").unwrap();
+
+        for mode in ["-i", "-E"] {
+            let m = matcher(&[mode, "-f", empty.to_str().unwrap()]);
+            assert!(m.search("anything\n\n", false).is_empty(), "{mode}");
+        }
+    }
+
+    #[test]
+    fn replace_literal_matches() {
+        let m = matcher(&["-i", "frog"]);
+        let line = "Frog and frog";
+
+        let (replaced, spans) = m.replace(line, &m.find_spans(line), "toad");
+
+        assert_eq!("toad and toad", replaced);
+        assert_eq!(vec![0..4, 9..13], spans);
+    }
+
+    #[test]
+    fn replace_expands_capture_groups() {
+        let m = matcher(&["-E", r"(\w+)\.unwrap\(\)"]);
+        let line = "let x = y.unwrap();";
+
+        let (replaced, spans) = m.replace(line, &m.find_spans(line), "${1}?");
+
+        assert_eq!("let x = y?;", replaced);
+        assert_eq!(vec![8..10], spans);
+    }
+
+    #[test]
+    fn literal_search_across_buffer_boundaries() {
+        let contents = b"a frog\r\nno\n\xff toad and frog\nfrogs\nlast frog";
+        let expected = vec![
+            (1, 0, String::from("a frog"), vec![2..6]),
+            (3, 11, String::from("\u{fffd} toad and frog"), vec![13..17]),
+            (4, 27, String::from("frogs"), vec![0..4]),
+            (5, 33, String::from("last frog"), vec![5..9]),
+        ];
+
+        for m in [matcher(&["frog"]), matcher(&["-e", "frog", "-e", "rog"])] {
+            // a tiny buffer cuts most lines in two
+            for capacity in [1, 3, 8, 1024] {
+                let reader = io::BufReader::with_capacity(capacity, &contents[..]);
+                let mut found = Vec::new();
+                m.search_reader(reader, false, 0, 0, |entry| {
+                    if let Entry::Match(r) = entry {
+                        found.push((r.line_number, r.byte_offset, r.line.to_string(), r.spans));
+                    }
+                    Ok(true)
+                })
+                .unwrap();
+
+                assert_eq!(expected, found, "buffer of {capacity}");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_pattern_among_several_matches_each_line_once() {
+        let m = matcher(&["-e", "", "-e", "foo"]);
+
+        assert_eq!(vec!["a", "b"], lines(&m.search("a\nb\n", false)));
+        assert_eq!(vec!["a", "b"], lines(&m.search("a\nb", false)));
+
+        // and the end of input on its own is no line to report
+        let m = matcher(&["-e", "a", "-e", "foo"]);
+        let mut found = 0;
+        m.search_reader("a\n".as_bytes(), false, 0, 0, |_| {
+            found += 1;
+            Ok(true)
+        })
+        .unwrap();
+        assert_eq!(1, found);
+    }
+
+    #[test]
+    fn multiline_matches_span_lines() {
+        let m = matcher(&["-U", "-E", r"fn \w+\(\n\s+config"]);
+        let contents = "fn run(\n    config: Config,\n) {}\n";
+
+        assert_eq!(
+            vec![Entry::Match(Match {
+                line_number: 1,
+                byte_offset: 0,
+                line: "fn run(\n    config: Config,",
+                spans: vec![0..18],
+                distance: 0,
+            })],
+            m.search_with_context(contents, false, 0, 0)
+        );
+    }
+
+    #[test]
+    fn multiline_context_and_invert() {
+        let m = matcher(&["-U", "-E", r"foo\nbar"]);
+        let contents = "a\nfoo\nbar\nb\nc\nd\nfoo\nbar\n";
+
+        let entries = m.search_with_context(contents, false, 0, 1);
+        let kinds: Vec<(&str, usize)> = entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Match(m) => ("match", m.line_number),
+                Entry::Context(m) => ("context", m.line_number),
+                Entry::Break => ("break", 0),
+            })
+            .collect();
+        assert_eq!(
+            vec![("match", 2), ("context", 4), ("break", 0), ("match", 7)],
+            kinds
+        );
+
+        assert_eq!(vec!["a", "b", "c", "d"], lines(&m.search(contents, true)));
+    }
+
+    #[test]
+    fn fuzzy_matches_typos() {
+        let m = matcher(&["--fuzzy=1", "frog"]);
+
+        assert_eq!(vec![2..6], m.find_spans("a frig"));
+        assert_eq!(Some(1), m.distance("a frig"));
+        assert_eq!(Some(0), m.distance("a frog"));
+        assert_eq!(None, m.distance("a toad"));
+
+        let distances: Vec<usize> = m
+            .search("a frig\na toad\na frog", false)
+            .iter()
+            .map(|result| result.distance)
+            .collect();
+        assert_eq!(vec![1, 0], distances);
+    }
+
+    #[test]
+    fn smart_case_looks_for_uppercase() {
+        assert!(!has_uppercase("frog", false));
+        assert!(has_uppercase("Frog", false));
+        assert!(!has_uppercase(r"\Wfrog\S", true));
+        assert!(has_uppercase(r"\wFrog", true));
+    }
+}
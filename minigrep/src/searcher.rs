@@ -0,0 +1,622 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+
+use crate::printer::{self, Printer, Stats};
+use crate::search::{Entry, Matcher};
+use crate::{decompress, index, input, replace, walk, Config, Error, OutputMode};
+
+/// A search that is ready to go: the `Config` it was built from, and the
+/// `Matcher` compiled from its patterns.
+///
+/// `print` does everything the `minigrep` binary does, writing to any
+/// `io::Write`; `search_reader` and `search_path` hand each `Entry` to a
+/// callback instead, for callers that want the results themselves.
+#[derive(Debug)]
+pub struct Searcher {
+    config: Config,
+    matcher: Matcher,
+}
+
+/// How a search that ran to the end went.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Whether any line was selected.
+    pub matched: bool,
+    /// Inputs that couldn't be searched and directory entries that couldn't
+    /// be read. The search carried on without them.
+    pub errors: Vec<Error>,
+    /// Problems that didn't change the results, like `--index` finding no
+    /// usable index and searching every file instead.
+    pub warnings: Vec<Error>,
+}
+
+/// Something to search, in the order its results are printed.
+enum Input {
+    Stdin,
+    /// `explicit` files were named on the command line rather than found
+    /// while walking a directory.
+    File {
+        path: PathBuf,
+        explicit: bool,
+    },
+}
+
+/// What a worker thread sends the thread writing the output, tagged with
+/// the index of the input it concerns.
+enum Message {
+    /// The next piece of the input's output.
+    Output(usize, Vec<u8>),
+    /// The input has been searched, or searching it failed.
+    Done(usize, io::Result<Stats>),
+    /// Standard input can't be handed to a worker, the writer reads it itself.
+    Stdin(usize),
+}
+
+// workers hand over their output in pieces of about this size
+const CHUNK_LEN: usize = 64 * 1024;
+// how much output a worker may have waiting to be written before it stops
+// until its input is the one being written; this keeps memory bounded when
+// a large file is searched while an earlier one is still being printed
+const AHEAD_LIMIT: usize = 1 << 20;
+
+/// The index of the input whose output is being written. Workers that have
+/// too much output waiting wait for it to reach their own input.
+struct Turn {
+    current: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl Turn {
+    fn set(&self, index: usize) {
+        *self.current.lock().unwrap() = index;
+        self.changed.notify_all();
+    }
+
+    fn wait_for(&self, index: usize) {
+        let mut current = self.current.lock().unwrap();
+        while *current < index {
+            current = self.changed.wait(current).unwrap();
+        }
+    }
+}
+
+/// Where a worker's `Printer` writes: output is passed on to the writing
+/// thread in chunks as it is produced.
+struct Chunks<'a> {
+    index: usize,
+    buffer: Vec<u8>,
+    sent: usize,
+    tx: &'a mpsc::SyncSender<Message>,
+    turn: &'a Turn,
+}
+
+impl Chunks<'_> {
+    fn send(&mut self) -> io::Result<()> {
+        self.sent += self.buffer.len();
+        if self.sent > AHEAD_LIMIT {
+            self.turn.wait_for(self.index);
+        }
+
+        let chunk = mem::take(&mut self.buffer);
+        // the writer only hangs up once it has given up on the remaining files
+        self.tx
+            .send(Message::Output(self.index, chunk))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl Write for Chunks<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_LEN {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            Ok(())
+        } else {
+            self.send()
+        }
+    }
+}
+
+impl Searcher {
+    /// Compiles the patterns in `config`, so a bad pattern surfaces here as
+    /// an error rather than later as a panic.
+    pub fn new(config: Config) -> Result<Searcher, Error> {
+        let matcher = Matcher::new(&config)?;
+
+        Ok(Searcher { config, matcher })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    /// Searches `reader`, handing each matching line, context line and group
+    /// break to `sink` as it is found. `sink` returns whether to keep going.
+    pub fn search_reader<R: BufRead>(
+        &self,
+        reader: R,
+        sink: impl FnMut(Entry<'_>) -> io::Result<bool>,
+    ) -> io::Result<()> {
+        self.matcher.search_reader(
+            reader,
+            self.config.invert_match,
+            self.config.before_context,
+            self.config.after_context,
+            sink,
+        )
+    }
+
+    /// Like `search_reader`, for the file at `path`.
+    pub fn search_path(
+        &self,
+        path: &Path,
+        sink: impl FnMut(Entry<'_>) -> io::Result<bool>,
+    ) -> io::Result<()> {
+        self.search_reader(input::open(path, &self.config)?, sink)
+    }
+
+    /// Searches every path in the config, walking directories and reading
+    /// standard input for `-`, and writes the results to `out` in the form
+    /// the config asks for.
+    ///
+    /// Problems with single inputs don't stop the search; they are
+    /// collected in the `Report` instead.
+    pub fn print<W: Write>(&self, out: W) -> Result<Report, Error> {
+        let config = &self.config;
+
+        let mut inputs = Vec::new();
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+        // files found in a directory are always labelled with their path
+        let mut walked = false;
+        for file_path in &config.file_paths {
+            let path = Path::new(file_path);
+
+            if file_path == "-" {
+                inputs.push(Input::Stdin);
+            } else if path.is_dir() {
+                walked = true;
+                let files = walk::files(path, config).map_err(|source| Error::Walk {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+                errors.extend(files.errors.into_iter().map(Error::Entry));
+                let filter = if config.use_index {
+                    index::Filter::new(path, config).unwrap_or_else(|e| {
+                        warnings.push(e);
+                        None
+                    })
+                } else {
+                    None
+                };
+                for file in files.paths {
+                    if filter
+                        .as_ref()
+                        .is_some_and(|filter| !filter.may_match(&file))
+                    {
+                        continue;
+                    }
+                    inputs.push(Input::File {
+                        path: file,
+                        explicit: false,
+                    });
+                }
+            } else {
+                inputs.push(Input::File {
+                    path: path.to_path_buf(),
+                    explicit: true,
+                });
+            }
+        }
+
+        if config.in_place && inputs.iter().any(|input| matches!(input, Input::Stdin)) {
+            return Err(Error::InPlaceStdin);
+        }
+
+        let show_names = config.file_paths.len() > 1 || walked;
+        let threads = config.threads.min(inputs.len()).max(1);
+        let mut out = BufWriter::new(out);
+
+        let totals = if threads == 1 {
+            // nothing to do in parallel, so write straight to the output
+            let mut totals = Stats::default();
+            for input in &inputs {
+                let result = self.search_input(input, show_names, &mut out);
+                totals += settle(input, result, &mut errors)?;
+            }
+            totals
+        } else {
+            self.search_parallel(&inputs, threads, show_names, &mut out, &mut errors)?
+        };
+
+        if config.output == OutputMode::Json {
+            printer::write_summary(&mut out, totals).map_err(Error::Output)?;
+        }
+        out.flush().map_err(Error::Output)?;
+
+        Ok(Report {
+            matched: totals.matched_lines > 0,
+            errors,
+            warnings,
+        })
+    }
+
+    /// Searches `inputs` on `threads` worker threads, writing each one's
+    /// output whole and in order. The output of the input being written goes
+    /// straight through; workers that are ahead buffer theirs, up to a limit.
+    fn search_parallel<W: Write>(
+        &self,
+        inputs: &[Input],
+        threads: usize,
+        show_names: bool,
+        out: &mut W,
+        errors: &mut Vec<Error>,
+    ) -> Result<Stats, Error> {
+        // index of the next input a worker should pick up
+        let next = AtomicUsize::new(0);
+        let turn = Turn {
+            current: Mutex::new(0),
+            changed: Condvar::new(),
+        };
+
+        thread::scope(|scope| {
+            let (tx, rx) = mpsc::sync_channel(threads * 4);
+
+            for _ in 0..threads {
+                let tx = tx.clone();
+                let (next, turn) = (&next, &turn);
+
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(input) = inputs.get(index) else {
+                        break;
+                    };
+
+                    let message = match input {
+                        Input::Stdin => Message::Stdin(index),
+                        Input::File { .. } => {
+                            let mut chunks = Chunks {
+                                index,
+                                buffer: Vec::new(),
+                                sent: 0,
+                                tx: &tx,
+                                turn,
+                            };
+                            let result = self
+                                .search_input(input, show_names, &mut chunks)
+                                .and_then(|stats| chunks.flush().map(|()| stats));
+                            Message::Done(index, result)
+                        }
+                    };
+
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(tx);
+
+            // whichever way this ends, wake any worker still waiting its
+            // turn so it can find out nobody is listening and stop
+            let totals = self.write_in_order(inputs, &rx, &turn, show_names, out, errors);
+            drop(rx);
+            turn.set(usize::MAX);
+            totals
+        })
+    }
+
+    /// Copies the workers' output to `out` input by input, holding on to
+    /// anything that arrives for a later input until its turn. Standard input
+    /// is searched here when its turn comes.
+    fn write_in_order<W: Write>(
+        &self,
+        inputs: &[Input],
+        rx: &mpsc::Receiver<Message>,
+        turn: &Turn,
+        show_names: bool,
+        out: &mut W,
+        errors: &mut Vec<Error>,
+    ) -> Result<Stats, Error> {
+        let mut early: HashMap<usize, Vec<Message>> = HashMap::new();
+        let mut totals = Stats::default();
+
+        for (index, input) in inputs.iter().enumerate() {
+            turn.set(index);
+            let mut waiting = early.remove(&index).unwrap_or_default().into_iter();
+
+            loop {
+                let message = match waiting.next() {
+                    Some(message) => message,
+                    None => rx.recv().expect("a worker thread panicked"),
+                };
+                let for_index = match &message {
+                    Message::Output(i, _) | Message::Done(i, _) | Message::Stdin(i) => *i,
+                };
+                if for_index != index {
+                    early.entry(for_index).or_default().push(message);
+                    continue;
+                }
+
+                match message {
+                    Message::Output(_, chunk) => out.write_all(&chunk).map_err(Error::Output)?,
+                    Message::Done(_, result) => {
+                        totals += settle(input, result, errors)?;
+                        break;
+                    }
+                    Message::Stdin(_) => {
+                        let result = self.search_input(input, show_names, &mut *out);
+                        totals += settle(input, result, errors)?;
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(totals)
+    }
+
+    /// Searches one input, printing the results to `out`. Standard input is
+    /// read here too, so this must run on the thread that owns it.
+    fn search_input<W: Write>(&self, input: &Input, show_names: bool, out: W) -> io::Result<Stats> {
+        let (config, matcher) = (&self.config, &self.matcher);
+
+        match input {
+            Input::Stdin => {
+                let mut printer = Printer::new(config, out);
+                let stdin = io::stdin().lock();
+                if config.search_zip {
+                    let reader = decompress::reader(stdin)?;
+                    printer.search(matcher, reader, "(standard input)", show_names)?;
+                } else {
+                    printer.search(matcher, stdin, "(standard input)", show_names)?;
+                }
+                Ok(printer.stats())
+            }
+            Input::File { path, .. } if config.in_place => {
+                let replacement = config.replace.as_deref().unwrap_or_default();
+                let changed =
+                    replace::rewrite_file(matcher, path, replacement, &config.backup_suffix)?;
+                Ok(Stats {
+                    files_searched: 1,
+                    files_with_matches: usize::from(changed > 0),
+                    matched_lines: changed,
+                    matches: 0,
+                })
+            }
+            Input::File { path, explicit } => {
+                let mut printer = Printer::new(config, out);
+                search_file(config, matcher, &mut printer, path, *explicit, show_names)?;
+                Ok(printer.stats())
+            }
+        }
+    }
+}
+
+/// Turns the result of searching `input` into its share of the totals, or
+/// the error that ends the search. Errors that don't end it go to `errors`.
+fn settle(
+    input: &Input,
+    result: io::Result<Stats>,
+    errors: &mut Vec<Error>,
+) -> Result<Stats, Error> {
+    let e = match result {
+        Ok(stats) => return Ok(stats),
+        Err(e) => e,
+    };
+
+    // reading and printing are interleaved, but a closed pipe can only be
+    // our output
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        return Err(Error::Output(e));
+    }
+
+    // like grep, one input that can't be read doesn't stop the others
+    let path = match input {
+        Input::Stdin => PathBuf::from("-"),
+        Input::File { path, .. } => path.clone(),
+    };
+    errors.push(Error::Io { path, source: e });
+    Ok(Stats::default())
+}
+
+/// Searches one file. Files found while walking a directory are skipped if
+/// they look binary; ones named explicitly are always searched.
+fn search_file<W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    printer: &mut Printer<W>,
+    path: &Path,
+    explicit: bool,
+    show_name: bool,
+) -> io::Result<()> {
+    let mut reader = input::open(path, config)?;
+
+    // the first buffer-full is enough to spot a binary file
+    if !explicit && walk::is_binary(reader.fill_buf()?) {
+        return Ok(());
+    }
+
+    printer.search(matcher, reader, &path.display().to_string(), show_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::config::test_config;
+
+    fn searcher(args: &[&str]) -> Searcher {
+        Searcher::new(test_config(args)).unwrap()
+    }
+
+    #[test]
+    fn sink_sees_every_entry() {
+        let searcher = searcher(&["-A", "1", "frog"]);
+        let mut entries = Vec::new();
+
+        searcher
+            .search_reader("a frog\nin a bog\nnothing\n".as_bytes(), |entry| {
+                entries.push(match entry {
+                    Entry::Match(m) => format!("match {}", m.line),
+                    Entry::Context(m) => format!("context {}", m.line),
+                    Entry::Break => String::from("break"),
+                });
+                Ok(true)
+            })
+            .unwrap();
+
+        assert_eq!(vec!["match a frog", "context in a bog"], entries);
+    }
+
+    #[test]
+    fn sink_can_stop_early() {
+        let searcher = searcher(&["o"]);
+        let mut seen = 0;
+
+        searcher
+            .search_reader("one\ntwo\nthree\n".as_bytes(), |_| {
+                seen += 1;
+                Ok(false)
+            })
+            .unwrap();
+
+        assert_eq!(1, seen);
+    }
+
+    #[test]
+    fn prints_to_any_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        fs::write(&path, "I'm nobody! Who are you?\nHow public, like a frog\n").unwrap();
+        let searcher = searcher(&["-n", "frog", path.to_str().unwrap()]);
+        let mut out = Vec::new();
+
+        assert!(searcher.print(&mut out).unwrap().matched);
+        assert_eq!(
+            "2:How public, like a frog\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn parallel_output_is_in_order() {
+        // each file prints more than a worker may hold back
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = Vec::new();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let path = dir.path().join(name);
+            let line = format!("{name}: How public, like a frog\n");
+            fs::write(&path, line.repeat(2 * AHEAD_LIMIT / line.len())).unwrap();
+            paths.push(path.to_str().unwrap().to_string());
+        }
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+
+        let output = |threads: &str| {
+            let mut args = vec!["-j", threads, "frog"];
+            args.extend(&paths);
+            let mut out = Vec::new();
+            searcher(&args).print(&mut out).unwrap();
+            out
+        };
+
+        assert!(output("3") == output("1"));
+    }
+
+    #[test]
+    fn errors_carry_the_path() {
+        let searcher = searcher(&["frog", "no/such/poem.txt"]);
+
+        match &searcher.print(io::sink()).unwrap().errors[..] {
+            [Error::Io { path, source }] => {
+                assert_eq!(Path::new("no/such/poem.txt"), path);
+                assert_eq!(io::ErrorKind::NotFound, source.kind());
+            }
+            other => panic!("expected a not found error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn a_missing_file_does_not_stop_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        fs::write(&path, "How public, like a frog\n").unwrap();
+        let path = path.to_str().unwrap();
+        let mut out = Vec::new();
+
+        for threads in ["1", "2"] {
+            let searcher = searcher(&["-j", threads, "frog", "no/such/poem.txt", path]);
+            out.clear();
+
+            let report = searcher.print(&mut out).unwrap();
+            assert!(report.matched);
+            assert_eq!(1, report.errors.len());
+            assert!(String::from_utf8_lossy(&out).ends_with(":How public, like a frog\n"));
+        }
+    }
+
+    #[test]
+    fn nothing_selected_is_not_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        fs::write(&path, "I'm nobody! Who are you?\n").unwrap();
+
+        assert!(
+            !searcher(&["frog", path.to_str().unwrap()])
+                .print(io::sink())
+                .unwrap()
+                .matched
+        );
+    }
+
+    #[test]
+    fn config_needs_no_command_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        fs::write(&path, "I'm nobody! Who are you?\nHow public, like a FROG\n").unwrap();
+        let config = Config {
+            patterns: vec![String::from("frog")],
+            file_paths: vec![path.to_str().unwrap().to_string()],
+            ignore_case: true,
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+
+        assert!(
+            Searcher::new(config)
+                .unwrap()
+                .print(&mut out)
+                .unwrap()
+                .matched
+        );
+        assert_eq!("How public, like a FROG\n", String::from_utf8(out).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_files_in_a_directory_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("poem.txt"), "How public, like a frog\n").unwrap();
+        std::os::unix::fs::symlink("no/such/file", dir.path().join("dangling")).unwrap();
+        let searcher = searcher(&["frog", dir.path().to_str().unwrap()]);
+
+        let report = searcher.print(io::sink()).unwrap();
+        assert!(report.matched);
+        assert_eq!(1, report.errors.len());
+    }
+}
@@ -0,0 +1,225 @@
+use std::path::{Path, PathBuf};
+
+use ignore::overrides::OverrideBuilder;
+use ignore::types::TypesBuilder;
+use ignore::WalkBuilder;
+
+use crate::{index, Config};
+
+/// What `files` found below a directory.
+#[derive(Debug, Default)]
+pub struct Files {
+    /// Every file to search, sorted.
+    pub paths: Vec<PathBuf>,
+    /// Why some entries couldn't be read.
+    pub errors: Vec<ignore::Error>,
+}
+
+// how much of a file we look at when deciding whether it is binary
+const BINARY_SNIFF_LEN: usize = 8192;
+
+/// Collects every regular file below `root`, recursing into subdirectories.
+///
+/// Unless `config` says otherwise, hidden files and anything matched by a
+/// `.gitignore` or `.ignore` file are left out, and `--glob`/`--type`
+/// filters narrow things down further. Symbolic links are followed when
+/// `config.follow_links` is set and skipped otherwise.
+///
+/// A bad glob or unknown file type is an error; entries that can't be read
+/// are skipped and handed back in `Files::errors`, so one bad directory
+/// doesn't abort the whole search.
+pub fn files(root: &Path, config: &Config) -> Result<Files, ignore::Error> {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in &config.globs {
+        overrides.add(glob)?;
+    }
+
+    let mut types = TypesBuilder::new();
+    types.add_defaults();
+    for (name, glob) in &config.type_defs {
+        types.add(name, glob)?;
+    }
+    for name in &config.types {
+        types.select(name);
+    }
+    for name in &config.types_not {
+        types.negate(name);
+    }
+
+    let walker = WalkBuilder::new(root)
+        .hidden(!config.hidden)
+        .ignore(config.use_ignore_files)
+        .git_ignore(config.use_ignore_files)
+        .git_global(config.use_ignore_files)
+        .git_exclude(config.use_ignore_files)
+        .parents(config.use_ignore_files)
+        // .gitignore still counts when the tree isn't a git checkout
+        .require_git(false)
+        .follow_links(config.follow_links)
+        .overrides(overrides.build()?)
+        .types(types.build()?)
+        .build();
+
+    let mut files = Files::default();
+
+    for entry in walker {
+        match entry {
+            // with --no-follow, links show up as symlinks and are skipped
+            Ok(entry) if entry.file_name() == index::FILE_NAME => {}
+            Ok(entry) if entry.file_type().is_some_and(|t| t.is_file()) => {
+                files.paths.push(entry.into_path());
+            }
+            Ok(_) => {}
+            Err(e) => files.errors.push(e),
+        }
+    }
+    files.paths.sort();
+
+    Ok(files)
+}
+
+/// Uses the same heuristic as grep: a NUL byte near the start means binary.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    let len = bytes.len().min(BINARY_SNIFF_LEN);
+    bytes[..len].contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::config::test_config;
+
+    fn names(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn finds_nested_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("top.txt"), "top").unwrap();
+        fs::write(dir.path().join("a/b/deep.txt"), "deep").unwrap();
+
+        let found = files(dir.path(), &test_config(&["query"])).unwrap().paths;
+
+        assert_eq!(
+            vec![dir.path().join("a/b/deep.txt"), dir.path().join("top.txt")],
+            found
+        );
+    }
+
+    #[test]
+    fn nul_bytes_mean_binary() {
+        assert!(is_binary(b"ELF\0\x01\x02"));
+        assert!(!is_binary("caf\u{e9} au lait".as_bytes()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_can_be_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        fs::write(target.path().join("linked.txt"), "linked").unwrap();
+        std::os::unix::fs::symlink(target.path(), dir.path().join("link")).unwrap();
+
+        assert_eq!(
+            1,
+            files(dir.path(), &test_config(&["query"]))
+                .unwrap()
+                .paths
+                .len()
+        );
+        assert!(files(dir.path(), &test_config(&["--no-follow", "query"]))
+            .unwrap()
+            .paths
+            .is_empty());
+    }
+
+    #[test]
+    fn ignore_files_and_hidden_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join(".ignore"), "*.log\n").unwrap();
+        fs::write(root.join("target/debug/build.txt"), "").unwrap();
+        fs::write(root.join("run.log"), "").unwrap();
+        fs::write(root.join(".env"), "").unwrap();
+        fs::write(root.join("main.rs"), "").unwrap();
+
+        assert_eq!(
+            vec!["main.rs"],
+            names(root, files(root, &test_config(&["query"])).unwrap().paths)
+        );
+        assert_eq!(
+            vec![".env", ".gitignore", ".ignore", "main.rs"],
+            names(
+                root,
+                files(root, &test_config(&["--hidden", "query"]))
+                    .unwrap()
+                    .paths
+            )
+        );
+        assert_eq!(
+            vec!["main.rs", "run.log", "target/debug/build.txt"],
+            names(
+                root,
+                files(root, &test_config(&["--no-ignore", "query"]))
+                    .unwrap()
+                    .paths
+            )
+        );
+    }
+
+    #[test]
+    fn globs_and_types_filter_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("lib.rs"), "").unwrap();
+        fs::write(root.join("notes.md"), "").unwrap();
+        fs::write(root.join("poem.txt"), "").unwrap();
+
+        assert_eq!(
+            vec!["lib.rs"],
+            names(
+                root,
+                files(root, &test_config(&["-t", "rust", "query"]))
+                    .unwrap()
+                    .paths
+            )
+        );
+        assert_eq!(
+            vec!["lib.rs", "poem.txt"],
+            names(
+                root,
+                files(root, &test_config(&["-T", "markdown", "query"]))
+                    .unwrap()
+                    .paths
+            )
+        );
+        assert_eq!(
+            vec!["notes.md", "poem.txt"],
+            names(
+                root,
+                files(root, &test_config(&["--glob=!*.rs", "query"]))
+                    .unwrap()
+                    .paths
+            )
+        );
+        assert_eq!(
+            vec!["poem.txt"],
+            names(
+                root,
+                files(root, &test_config(&["-g", "*.txt", "query"]))
+                    .unwrap()
+                    .paths
+            )
+        );
+        assert!(files(root, &test_config(&["--type", "no-such-type", "query"])).is_err());
+    }
+}